@@ -0,0 +1,728 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use eframe::egui::{self, Color32, Pos2, Rect};
+use eframe::emath::RectTransform;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationStyle {
+    pub color: Color32,
+    pub stroke_width: f32,
+    pub fill: Option<Color32>,
+    pub font_size: f32,
+}
+
+impl Default for AnnotationStyle {
+    fn default() -> Self {
+        Self {
+            color: Color32::WHITE,
+            stroke_width: 2.0,
+            fill: None,
+            font_size: 14.0,
+        }
+    }
+}
+
+impl AnnotationStyle {
+    pub fn scaled(&self, scale: f32) -> AnnotationStyle {
+        AnnotationStyle {
+            stroke_width: self.stroke_width * scale,
+            font_size: self.font_size * scale,
+            ..*self
+        }
+    }
+}
+
+// Raggio dei marcatori numerati, proporzionale alla dimensione del numero
+pub fn marker_radius(font_size: f32) -> f32 {
+    font_size * 0.9
+}
+
+// Smussa un tratto a mano libera con l'algoritmo di Chaikin, mantenendo gli estremi
+pub fn smooth_path(points: &[Pos2]) -> Vec<Pos2> {
+    let mut path = points.to_vec();
+    for _ in 0..2 {
+        if path.len() < 3 {
+            break;
+        }
+        let mut smoothed = vec![path[0]];
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            smoothed.push(a.lerp(b, 0.25));
+            smoothed.push(a.lerp(b, 0.75));
+        }
+        smoothed.push(path[path.len() - 1]);
+        path = smoothed;
+    }
+    path
+}
+
+fn distance_to_segment(p: Pos2, start: Pos2, end: Pos2) -> f32 {
+    let dir = end - start;
+    let length_sq = dir.length_sq();
+    let t = if length_sq > 0.0 { ((p - start).dot(dir) / length_sq).clamp(0.0, 1.0) } else { 0.0 };
+    (p - (start + dir * t)).length()
+}
+
+// Lunghezza e larghezza della punta delle frecce, proporzionali allo spessore del tratto
+pub fn arrowhead_size(stroke_width: f32) -> (f32, f32) {
+    (4.0 + 6.0 * stroke_width, 2.0 + 4.0 * stroke_width)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Annotation {
+    Rectangle {
+        rect: Rect,
+        style: AnnotationStyle,
+    },
+    Arrow {
+        start: Pos2,
+        end: Pos2,
+        style: AnnotationStyle,
+    },
+    Text {
+        pos: Pos2,
+        content: String,
+        style: AnnotationStyle,
+    },
+    Line {
+        start: Pos2,
+        end: Pos2,
+        style: AnnotationStyle,
+    },
+    Ellipse {
+        rect: Rect,
+        style: AnnotationStyle,
+    },
+    // Tratto a mano libera, usato sia dalla penna che dall'evidenziatore
+    Freehand {
+        points: Vec<Pos2>,
+        style: AnnotationStyle,
+    },
+    Marker {
+        pos: Pos2,
+        number: u32,
+        style: AnnotationStyle,
+    },
+    // Oscura tutto tranne rect (o l'ellisse inscritta); il colore dell'oscuramento è style.fill
+    Spotlight {
+        rect: Rect,
+        ellipse: bool,
+        style: AnnotationStyle,
+    },
+}
+
+pub const SPOTLIGHT_DIM: Color32 = Color32::from_rgba_premultiplied(0, 0, 0, 170);
+
+// Lente d'ingrandimento circolare: il caster la applica ai pixel catturati
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Magnifier {
+    pub center: Pos2,
+    pub radius: f32,
+    pub zoom: f32,
+}
+
+impl Annotation {
+    pub fn transformed(&self, transform: &RectTransform) -> Annotation {
+        let scale = transform.scale().x;
+        match self {
+            Annotation::Rectangle { rect, style } => Annotation::Rectangle {
+                rect: transform.transform_rect(*rect),
+                style: style.scaled(scale),
+            },
+            Annotation::Arrow { start, end, style } => Annotation::Arrow {
+                start: *transform * *start,
+                end: *transform * *end,
+                style: style.scaled(scale),
+            },
+            Annotation::Text { pos, content, style } => Annotation::Text {
+                pos: *transform * *pos,
+                content: content.clone(),
+                style: style.scaled(scale),
+            },
+            Annotation::Line { start, end, style } => Annotation::Line {
+                start: *transform * *start,
+                end: *transform * *end,
+                style: style.scaled(scale),
+            },
+            Annotation::Ellipse { rect, style } => Annotation::Ellipse {
+                rect: transform.transform_rect(*rect),
+                style: style.scaled(scale),
+            },
+            Annotation::Freehand { points, style } => Annotation::Freehand {
+                points: points.iter().map(|point| *transform * *point).collect(),
+                style: style.scaled(scale),
+            },
+            Annotation::Marker { pos, number, style } => Annotation::Marker {
+                pos: *transform * *pos,
+                number: *number,
+                style: style.scaled(scale),
+            },
+            Annotation::Spotlight { rect, ellipse, style } => Annotation::Spotlight {
+                rect: transform.transform_rect(*rect),
+                ellipse: *ellipse,
+                style: style.scaled(scale),
+            },
+        }
+    }
+
+    pub fn style(&self) -> &AnnotationStyle {
+        match self {
+            Annotation::Rectangle { style, .. }
+            | Annotation::Arrow { style, .. }
+            | Annotation::Text { style, .. }
+            | Annotation::Line { style, .. }
+            | Annotation::Ellipse { style, .. }
+            | Annotation::Freehand { style, .. }
+            | Annotation::Marker { style, .. }
+            | Annotation::Spotlight { style, .. } => style,
+        }
+    }
+
+    // Rettangolo che contiene l'annotazione, usato per evidenziare la selezione
+    pub fn bounds(&self) -> Rect {
+        match self {
+            Annotation::Rectangle { rect, style } | Annotation::Ellipse { rect, style } => {
+                rect.expand(style.stroke_width / 2.0)
+            }
+            Annotation::Arrow { start, end, style } | Annotation::Line { start, end, style } => {
+                Rect::from_two_pos(*start, *end).expand(style.stroke_width / 2.0)
+            }
+            Annotation::Text { pos, content, style } => {
+                // Stima delle dimensioni del testo, sufficiente per selezionarlo con il puntatore
+                let columns = content.lines().map(|line| line.chars().count()).max().unwrap_or(0) as f32;
+                let rows = content.lines().count().max(1) as f32;
+                Rect::from_min_size(*pos, egui::vec2(columns * style.font_size * 0.6, rows * style.font_size * 1.2))
+            }
+            Annotation::Freehand { points, style } => {
+                Rect::from_points(points).expand(style.stroke_width / 2.0)
+            }
+            Annotation::Marker { pos, style, .. } => {
+                Rect::from_center_size(*pos, egui::Vec2::splat(marker_radius(style.font_size) * 2.0))
+            }
+            Annotation::Spotlight { rect, .. } => *rect,
+        }
+    }
+
+    // Maniglie di ridimensionamento: gli angoli di rettangoli ed ellissi, gli estremi di frecce e linee
+    pub fn handles(&self) -> Vec<Pos2> {
+        match self {
+            Annotation::Rectangle { rect, .. } | Annotation::Ellipse { rect, .. } | Annotation::Spotlight { rect, .. } => {
+                vec![rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()]
+            }
+            Annotation::Arrow { start, end, .. } | Annotation::Line { start, end, .. } => vec![*start, *end],
+            _ => Vec::new(),
+        }
+    }
+
+    // Sposta la maniglia indicata in pos; per i rettangoli l'angolo opposto resta fermo
+    pub fn with_handle_moved(&self, handle: usize, pos: Pos2) -> Annotation {
+        let mut annotation = self.clone();
+        match &mut annotation {
+            Annotation::Rectangle { rect, .. } | Annotation::Ellipse { rect, .. } | Annotation::Spotlight { rect, .. } => {
+                let corners = [rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+                *rect = Rect::from_two_pos(corners[(handle + 2) % 4], pos);
+            }
+            Annotation::Arrow { start, end, .. } | Annotation::Line { start, end, .. } => {
+                if handle == 0 {
+                    *start = pos;
+                } else {
+                    *end = pos;
+                }
+            }
+            _ => {}
+        }
+        annotation
+    }
+
+    // Copia con colori di tratto e riempimento attenuati di `opacity` (0-1), per le annotazioni in dissolvenza
+    pub fn with_opacity(&self, opacity: f32) -> Annotation {
+        let fade = |color: Color32| {
+            let [r, g, b, a] = color.to_srgba_unmultiplied();
+            Color32::from_rgba_unmultiplied(r, g, b, (a as f32 * opacity.clamp(0.0, 1.0)).round() as u8)
+        };
+        let mut annotation = self.clone();
+        match &mut annotation {
+            Annotation::Rectangle { style, .. }
+            | Annotation::Arrow { style, .. }
+            | Annotation::Text { style, .. }
+            | Annotation::Line { style, .. }
+            | Annotation::Ellipse { style, .. }
+            | Annotation::Freehand { style, .. }
+            | Annotation::Marker { style, .. }
+            | Annotation::Spotlight { style, .. } => {
+                style.color = fade(style.color);
+                style.fill = style.fill.map(fade);
+            }
+        }
+        annotation
+    }
+
+    pub fn translated(&self, delta: egui::Vec2) -> Annotation {
+        let mut annotation = self.clone();
+        match &mut annotation {
+            Annotation::Rectangle { rect, .. } | Annotation::Ellipse { rect, .. } | Annotation::Spotlight { rect, .. } => {
+                *rect = rect.translate(delta)
+            }
+            Annotation::Arrow { start, end, .. } | Annotation::Line { start, end, .. } => {
+                *start += delta;
+                *end += delta;
+            }
+            Annotation::Text { pos, .. } | Annotation::Marker { pos, .. } => *pos += delta,
+            Annotation::Freehand { points, .. } => {
+                for point in points {
+                    *point += delta;
+                }
+            }
+        }
+        annotation
+    }
+
+    // Verifica se il punto cade sull'annotazione, con una tolleranza in unità delle annotazioni
+    pub fn hit(&self, pos: Pos2, tolerance: f32) -> bool {
+        match self {
+            Annotation::Rectangle { rect, style } => {
+                let margin = tolerance + style.stroke_width / 2.0;
+                if style.fill.is_some() {
+                    return rect.expand(margin).contains(pos);
+                }
+                rect.expand(margin).contains(pos) && !rect.shrink(margin).contains(pos)
+            }
+            Annotation::Arrow { start, end, style } | Annotation::Line { start, end, style } => {
+                distance_to_segment(pos, *start, *end) <= tolerance + style.stroke_width / 2.0
+            }
+            Annotation::Text { .. } => self.bounds().expand(tolerance).contains(pos),
+            Annotation::Ellipse { rect, style } => {
+                let radius = rect.size() / 2.0;
+                if radius.x <= 0.0 || radius.y <= 0.0 {
+                    return false;
+                }
+                let offset = pos - rect.center();
+                let distance = ((offset.x / radius.x).powi(2) + (offset.y / radius.y).powi(2)).sqrt();
+                let band = (tolerance + style.stroke_width / 2.0) / radius.x.min(radius.y);
+                if style.fill.is_some() {
+                    distance <= 1.0 + band
+                } else {
+                    (distance - 1.0).abs() <= band
+                }
+            }
+            Annotation::Freehand { points, style } => {
+                let margin = tolerance + style.stroke_width / 2.0;
+                match points.as_slice() {
+                    [point] => point.distance(pos) <= margin,
+                    _ => points.windows(2).any(|pair| distance_to_segment(pos, pair[0], pair[1]) <= margin),
+                }
+            }
+            Annotation::Marker { pos: center, style, .. } => {
+                center.distance(pos) <= marker_radius(style.font_size) + tolerance
+            }
+            // Lo spotlight si seleziona dal bordo, l'interno deve restare cliccabile
+            Annotation::Spotlight { rect, ellipse: false, .. } => {
+                rect.expand(tolerance).contains(pos) && !rect.shrink(tolerance).contains(pos)
+            }
+            Annotation::Spotlight { rect, ellipse: true, .. } => {
+                Annotation::Ellipse { rect: *rect, style: AnnotationStyle { stroke_width: 0.0, fill: None, ..AnnotationStyle::default() } }
+                    .hit(pos, tolerance)
+            }
+        }
+    }
+}
+
+// Operazioni reversibili sulla lista delle annotazioni
+#[derive(Debug, Clone)]
+pub enum AnnotationCommand {
+    Add { index: usize, annotation: Annotation },
+    Remove { index: usize, annotation: Annotation },
+    // Spostamento, ridimensionamento o modifica del testo
+    Replace { index: usize, before: Annotation, after: Annotation },
+    // Sostituzione dell'intera lista: cancellazione o caricamento da file
+    SetAll { before: Vec<Annotation>, after: Vec<Annotation> },
+}
+
+impl AnnotationCommand {
+    fn apply(&self, annotations: &mut Vec<Annotation>) {
+        match self {
+            AnnotationCommand::Add { index, annotation } => {
+                annotations.insert((*index).min(annotations.len()), annotation.clone());
+            }
+            AnnotationCommand::Remove { index, .. } => {
+                if *index < annotations.len() {
+                    annotations.remove(*index);
+                }
+            }
+            AnnotationCommand::Replace { index, after, .. } => {
+                if let Some(annotation) = annotations.get_mut(*index) {
+                    *annotation = after.clone();
+                }
+            }
+            AnnotationCommand::SetAll { after, .. } => *annotations = after.clone(),
+        }
+    }
+
+    fn revert(&self, annotations: &mut Vec<Annotation>) {
+        match self {
+            AnnotationCommand::Add { index, .. } => {
+                if *index < annotations.len() {
+                    annotations.remove(*index);
+                }
+            }
+            AnnotationCommand::Remove { index, annotation } => {
+                annotations.insert((*index).min(annotations.len()), annotation.clone());
+            }
+            AnnotationCommand::Replace { index, before, .. } => {
+                if let Some(annotation) = annotations.get_mut(*index) {
+                    *annotation = before.clone();
+                }
+            }
+            AnnotationCommand::SetAll { before, .. } => *annotations = before.clone(),
+        }
+    }
+}
+
+const HISTORY_LIMIT: usize = 200;
+
+#[derive(Debug, Default)]
+pub struct AnnotationHistory {
+    undo_stack: Vec<AnnotationCommand>,
+    redo_stack: Vec<AnnotationCommand>,
+}
+
+impl AnnotationHistory {
+    // Applica il comando e lo registra; un nuovo comando invalida i passi annullati
+    pub fn execute(&mut self, command: AnnotationCommand, annotations: &mut Vec<Annotation>) {
+        command.apply(annotations);
+        self.undo_stack.push(command);
+        if self.undo_stack.len() > HISTORY_LIMIT {
+            self.undo_stack.remove(0);
+        }
+        self.redo_stack.clear();
+    }
+
+    pub fn undo(&mut self, annotations: &mut Vec<Annotation>) {
+        if let Some(command) = self.undo_stack.pop() {
+            command.revert(annotations);
+            self.redo_stack.push(command);
+        }
+    }
+
+    pub fn redo(&mut self, annotations: &mut Vec<Annotation>) {
+        if let Some(command) = self.redo_stack.pop() {
+            command.apply(annotations);
+            self.undo_stack.push(command);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+}
+
+const ANNOTATION_SET_VERSION: u32 = 1;
+
+// File JSON delle annotazioni: le coordinate sono relative all'area catturata (da 0 a 1),
+// così le annotazioni preparate restano valide anche se cambia la risoluzione
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnnotationSet {
+    pub version: u32,
+    pub annotations: Vec<Annotation>,
+}
+
+impl AnnotationSet {
+    pub fn from_area(annotations: &[Annotation], area: Rect) -> Self {
+        let to_unit = RectTransform::from_to(area, Rect::from_min_size(Pos2::ZERO, egui::Vec2::splat(1.0)));
+        Self {
+            version: ANNOTATION_SET_VERSION,
+            annotations: annotations.iter().map(|annotation| annotation.transformed(&to_unit)).collect(),
+        }
+    }
+
+    pub fn to_area(&self, area: Rect) -> Vec<Annotation> {
+        let from_unit = RectTransform::from_to(Rect::from_min_size(Pos2::ZERO, egui::Vec2::splat(1.0)), area);
+        self.annotations.iter().map(|annotation| annotation.transformed(&from_unit)).collect()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        let set: AnnotationSet = serde_json::from_str(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if set.version > ANNOTATION_SET_VERSION {
+            return Err(invalid("File delle annotazioni creato da una versione più recente"));
+        }
+        Ok(set)
+    }
+}
+
+// I messaggi di annotazione viaggiano sullo stesso stream dei frame JPEG (che iniziano sempre con 0xFF):
+// il primo byte del payload li distingue
+pub const ANNOTATION_TAG: u8 = b'A';
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationMessage {
+    // Mantiene solo le prime n annotazioni
+    Truncate(u32),
+    // Imposta l'annotazione in posizione index, scartando quelle successive
+    Set { index: u32, annotation: Annotation },
+}
+
+impl AnnotationMessage {
+    // Messaggi che portano la lista `old` a coincidere con `new`, a partire dal primo elemento diverso
+    pub fn diff(old: &[Annotation], new: &[Annotation]) -> Vec<AnnotationMessage> {
+        let common = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let mut messages = Vec::new();
+        if common < old.len() {
+            messages.push(AnnotationMessage::Truncate(common as u32));
+        }
+        for (index, annotation) in new.iter().enumerate().skip(common) {
+            messages.push(AnnotationMessage::Set { index: index as u32, annotation: annotation.clone() });
+        }
+        messages
+    }
+
+    // Messaggi che sostituiscono l'intera lista del receiver, qualunque cosa contenga
+    pub fn full(annotations: &[Annotation]) -> Vec<AnnotationMessage> {
+        let mut messages = vec![AnnotationMessage::Truncate(0)];
+        messages.extend(Self::diff(&[], annotations));
+        messages
+    }
+
+    pub fn apply(self, annotations: &mut Vec<Annotation>) {
+        match self {
+            AnnotationMessage::Truncate(len) => annotations.truncate(len as usize),
+            AnnotationMessage::Set { index, annotation } => {
+                let index = (index as usize).min(annotations.len());
+                annotations.truncate(index);
+                annotations.push(annotation);
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![ANNOTATION_TAG];
+        match self {
+            AnnotationMessage::Truncate(len) => {
+                data.push(0);
+                data.extend_from_slice(&len.to_be_bytes());
+            }
+            AnnotationMessage::Set { index, annotation } => {
+                data.push(1);
+                data.extend_from_slice(&index.to_be_bytes());
+                encode_annotation(annotation, &mut data);
+            }
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<AnnotationMessage> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.u8()? != ANNOTATION_TAG {
+            return Err(invalid("Messaggio di annotazione non valido"));
+        }
+        match reader.u8()? {
+            0 => Ok(AnnotationMessage::Truncate(reader.u32()?)),
+            1 => {
+                let index = reader.u32()?;
+                let annotation = decode_annotation(&mut reader)?;
+                Ok(AnnotationMessage::Set { index, annotation })
+            }
+            _ => Err(invalid("Tipo di messaggio di annotazione sconosciuto")),
+        }
+    }
+}
+
+fn encode_style(style: &AnnotationStyle, data: &mut Vec<u8>) {
+    data.extend_from_slice(&style.color.to_srgba_unmultiplied());
+    data.extend_from_slice(&style.stroke_width.to_be_bytes());
+    match style.fill {
+        Some(fill) => {
+            data.push(1);
+            data.extend_from_slice(&fill.to_srgba_unmultiplied());
+        }
+        None => data.push(0),
+    }
+    data.extend_from_slice(&style.font_size.to_be_bytes());
+}
+
+fn encode_annotation(annotation: &Annotation, data: &mut Vec<u8>) {
+    let put_f32 = |data: &mut Vec<u8>, value: f32| data.extend_from_slice(&value.to_be_bytes());
+    match annotation {
+        Annotation::Rectangle { rect, style } => {
+            data.push(0);
+            encode_style(style, data);
+            for value in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+                put_f32(data, value);
+            }
+        }
+        Annotation::Arrow { start, end, style } => {
+            data.push(1);
+            encode_style(style, data);
+            for value in [start.x, start.y, end.x, end.y] {
+                put_f32(data, value);
+            }
+        }
+        Annotation::Text { pos, content, style, .. } => {
+            data.push(2);
+            encode_style(style, data);
+            put_f32(data, pos.x);
+            put_f32(data, pos.y);
+            data.extend_from_slice(&(content.len() as u32).to_be_bytes());
+            data.extend_from_slice(content.as_bytes());
+        }
+        Annotation::Line { start, end, style } => {
+            data.push(3);
+            encode_style(style, data);
+            for value in [start.x, start.y, end.x, end.y] {
+                put_f32(data, value);
+            }
+        }
+        Annotation::Ellipse { rect, style } => {
+            data.push(4);
+            encode_style(style, data);
+            for value in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+                put_f32(data, value);
+            }
+        }
+        Annotation::Freehand { points, style } => {
+            data.push(5);
+            encode_style(style, data);
+            data.extend_from_slice(&(points.len() as u32).to_be_bytes());
+            for point in points {
+                put_f32(data, point.x);
+                put_f32(data, point.y);
+            }
+        }
+        Annotation::Marker { pos, number, style } => {
+            data.push(6);
+            encode_style(style, data);
+            put_f32(data, pos.x);
+            put_f32(data, pos.y);
+            data.extend_from_slice(&number.to_be_bytes());
+        }
+        Annotation::Spotlight { rect, ellipse, style } => {
+            data.push(7);
+            encode_style(style, data);
+            data.push(*ellipse as u8);
+            for value in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+                put_f32(data, value);
+            }
+        }
+    }
+}
+
+fn decode_style(reader: &mut Reader) -> io::Result<AnnotationStyle> {
+    let color = reader.color()?;
+    let stroke_width = reader.f32()?;
+    let fill = match reader.u8()? {
+        0 => None,
+        _ => Some(reader.color()?),
+    };
+    let font_size = reader.f32()?;
+    Ok(AnnotationStyle { color, stroke_width, fill, font_size })
+}
+
+fn decode_annotation(reader: &mut Reader) -> io::Result<Annotation> {
+    let kind = reader.u8()?;
+    let style = decode_style(reader)?;
+    match kind {
+        0 => {
+            let min = reader.pos2()?;
+            let max = reader.pos2()?;
+            Ok(Annotation::Rectangle { rect: Rect::from_min_max(min, max), style })
+        }
+        1 => {
+            let start = reader.pos2()?;
+            let end = reader.pos2()?;
+            Ok(Annotation::Arrow { start, end, style })
+        }
+        2 => {
+            let pos = reader.pos2()?;
+            let len = reader.u32()? as usize;
+            let content = String::from_utf8(reader.bytes(len)?.to_vec())
+                .map_err(|_| invalid("Testo dell'annotazione non valido"))?;
+            Ok(Annotation::Text { pos, content, style })
+        }
+        3 => {
+            let start = reader.pos2()?;
+            let end = reader.pos2()?;
+            Ok(Annotation::Line { start, end, style })
+        }
+        4 => {
+            let min = reader.pos2()?;
+            let max = reader.pos2()?;
+            Ok(Annotation::Ellipse { rect: Rect::from_min_max(min, max), style })
+        }
+        5 => {
+            let count = reader.u32()? as usize;
+            let points = (0..count).map(|_| reader.pos2()).collect::<io::Result<Vec<_>>>()?;
+            Ok(Annotation::Freehand { points, style })
+        }
+        6 => {
+            let pos = reader.pos2()?;
+            let number = reader.u32()?;
+            Ok(Annotation::Marker { pos, number, style })
+        }
+        7 => {
+            let ellipse = reader.u8()? != 0;
+            let min = reader.pos2()?;
+            let max = reader.pos2()?;
+            Ok(Annotation::Spotlight { rect: Rect::from_min_max(min, max), ellipse, style })
+        }
+        _ => Err(invalid("Tipo di annotazione sconosciuto")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.pos + len > self.data.len() {
+            return Err(invalid("Messaggio di annotazione troncato"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn pos2(&mut self) -> io::Result<Pos2> {
+        Ok(Pos2::new(self.f32()?, self.f32()?))
+    }
+
+    fn color(&mut self) -> io::Result<Color32> {
+        let [r, g, b, a] = self.bytes(4)?.try_into().unwrap();
+        Ok(Color32::from_rgba_unmultiplied(r, g, b, a))
+    }
+}
@@ -1,22 +1,35 @@
-use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::sync::{Arc, RwLock};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast;
 use std::error::Error;
 use std::sync::atomic::{AtomicBool, Ordering};
-use eframe::epaint::Rect;
+use eframe::epaint::{Pos2, Rect};
 use scrap::{Capturer, Display};
 use image::{ImageBuffer, RgbImage, DynamicImage};
 use tokio::io::AsyncWriteExt;
 use tokio::time::{sleep, Duration};
 use device_query::{DeviceQuery, DeviceState, Keycode};
+use crate::annotation::{Annotation, AnnotationMessage, Magnifier};
+use crate::content::ContentTracker;
+use crate::raster;
+use crate::scene::{Scene, SceneRenderer};
+use crate::slideshow::{Slideshow, SlideshowRenderer};
+use crate::whiteboard::{Whiteboard, WhiteboardRenderer};
 
 
 struct HotkeyState {
     paused: Arc<AtomicBool>,
     screen_blanked: Arc<AtomicBool>,
     terminate: Arc<AtomicBool>,
+    capture_settings: Arc<RwLock<CaptureSettings>>,
 }
 
+const SCENE_KEYS: [Keycode; 9] = [
+    Keycode::Key1, Keycode::Key2, Keycode::Key3,
+    Keycode::Key4, Keycode::Key5, Keycode::Key6,
+    Keycode::Key7, Keycode::Key8, Keycode::Key9,
+];
+
 
 fn handle_hotkeys(hotkey_state: Arc<HotkeyState>) {
     let device_state = DeviceState::new();
@@ -35,7 +48,33 @@ fn handle_hotkeys(hotkey_state: Arc<HotkeyState>) {
                 hotkey_state.screen_blanked.fetch_xor(true, Ordering::SeqCst);
                 println!("Schermo {}.", if hotkey_state.screen_blanked.load(Ordering::SeqCst) { "blanked" } else { "unblanked" });
             }
-            if keys.contains(&Keycode::Escape) {
+            if keys.contains(&Keycode::LControl) || keys.contains(&Keycode::RControl) {
+                if let Some(index) = SCENE_KEYS.iter().position(|key| keys.contains(key)) {
+                    if let Ok(mut settings) = hotkey_state.capture_settings.write() {
+                        if settings.source == CaptureSource::Scenes && index < settings.scenes.len() {
+                            settings.active_scene = index;
+                            println!("Scena attiva: {}", settings.scenes[index].name);
+                        }
+                    }
+                }
+            }
+            let next_slide = keys.contains(&Keycode::F4) || keys.contains(&Keycode::PageDown);
+            let previous_slide = keys.contains(&Keycode::F3) || keys.contains(&Keycode::PageUp);
+            if next_slide || previous_slide {
+                if let Ok(mut settings) = hotkey_state.capture_settings.write() {
+                    if settings.source == CaptureSource::Slideshow {
+                        if next_slide {
+                            settings.slideshow.next();
+                        } else {
+                            settings.slideshow.previous();
+                        }
+                        println!("Slide {}/{}", settings.slideshow.current + 1, settings.slideshow.slides.len());
+                    }
+                }
+            }
+            let escape_reserved = hotkey_state.capture_settings.read()
+                .is_ok_and(|settings| settings.escape_reserved_until.is_some_and(|until| std::time::Instant::now() < until));
+            if keys.contains(&Keycode::Escape) && !escape_reserved {
                 hotkey_state.terminate.store(true, Ordering::SeqCst);
                 println!("Terminazione richiesta.");
                 break;
@@ -47,6 +86,198 @@ fn handle_hotkeys(hotkey_state: Arc<HotkeyState>) {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayPlacement {
+    pub display_index: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayArrangement {
+    Horizontal,
+    Vertical,
+    Custom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptureSource {
+    Screen,
+    Scenes,
+    Slideshow,
+    Whiteboard,
+}
+
+pub struct CaptureSettings {
+    pub selected_area: Option<Rect>,
+    pub display_index: usize,
+    // Se non vuoto, i display indicati vengono catturati insieme e composti in un unico frame
+    pub combined_displays: Vec<DisplayPlacement>,
+    pub source: CaptureSource,
+    pub scenes: Vec<Scene>,
+    pub active_scene: usize,
+    pub slideshow: Slideshow,
+    pub whiteboard: Whiteboard,
+    // Annotazioni dell'overlay, in coordinate del frame trasmesso
+    pub annotations: Vec<Annotation>,
+    // Lente d'ingrandimento dell'overlay, in coordinate del frame
+    pub magnifier: Option<Magnifier>,
+    // Se attivo le annotazioni vengono disegnate nei frame invece di essere inviate come overlay vettoriale
+    pub burn_annotations: bool,
+    // Se attivo il caster riconosce i cambi di contenuto dell'area catturata e ne pubblica l'identificativo
+    pub content_pages: bool,
+    pub content_page: Option<u64>,
+    // Errore dell'ultimo cambio display: l'interfaccia lo mostra e riallinea la selezione a quella ripristinata
+    pub display_error: Option<String>,
+    // Fino a questo istante Esc serve all'overlay (testo in modifica o selezione) e non ferma il caster
+    pub escape_reserved_until: Option<std::time::Instant>,
+}
+
+impl CaptureSettings {
+    pub fn placements(&self) -> Vec<DisplayPlacement> {
+        if self.combined_displays.is_empty() {
+            vec![DisplayPlacement { display_index: self.display_index, x: 0, y: 0 }]
+        } else {
+            self.combined_displays.clone()
+        }
+    }
+
+    pub fn active_scene(&self) -> Option<&Scene> {
+        if self.source == CaptureSource::Scenes {
+            self.scenes.get(self.active_scene)
+        } else {
+            None
+        }
+    }
+}
+
+// Posizione massima di un display nella disposizione personalizzata: tutti i display affiancati o impilati.
+// Oltre questi limiti il canvas crescerebbe senza contenere altro che nero
+pub fn layout_limits(sizes: &[(usize, usize, usize)]) -> (usize, usize) {
+    (
+        sizes.iter().map(|&(_, width, _)| width).sum(),
+        sizes.iter().map(|&(_, _, height)| height).sum(),
+    )
+}
+
+// Calcola la posizione di ogni display nel canvas combinato; sizes contiene (indice, larghezza, altezza)
+pub fn arrange_displays(sizes: &[(usize, usize, usize)], arrangement: DisplayArrangement, current: &[DisplayPlacement]) -> Vec<DisplayPlacement> {
+    let (max_x, max_y) = layout_limits(sizes);
+    let mut offset = 0;
+    sizes.iter().map(|&(display_index, width, height)| {
+        match arrangement {
+            DisplayArrangement::Horizontal => {
+                let placement = DisplayPlacement { display_index, x: offset, y: 0 };
+                offset += width;
+                placement
+            }
+            DisplayArrangement::Vertical => {
+                let placement = DisplayPlacement { display_index, x: 0, y: offset };
+                offset += height;
+                placement
+            }
+            DisplayArrangement::Custom => {
+                let placement = current.iter()
+                    .find(|p| p.display_index == display_index)
+                    .copied()
+                    .unwrap_or(DisplayPlacement { display_index, x: 0, y: 0 });
+                DisplayPlacement { x: placement.x.min(max_x), y: placement.y.min(max_y), ..placement }
+            }
+        }
+    }).collect()
+}
+
+struct DisplaySource {
+    placement: DisplayPlacement,
+    capturer: Capturer,
+    width: usize,
+    height: usize,
+}
+
+// Insieme di display composti in un unico canvas BGRA. I display vengono letti uno dopo l'altro nello stesso
+// ciclo: i loro frame non sono presi nello stesso istante e il display più lento limita il frame rate
+pub(crate) struct DisplayCanvas {
+    sources: Vec<DisplaySource>,
+    buffer: Vec<u8>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl DisplayCanvas {
+    pub(crate) fn open(placements: &[DisplayPlacement]) -> Result<Self, Box<dyn Error>> {
+        if placements.is_empty() {
+            return Err("Nessun display selezionato".into());
+        }
+        let mut sources = Vec::new();
+        for placement in placements {
+            let displays = Display::all()?;
+            if placement.display_index >= displays.len() {
+                return Err("Indice del display non valido".into());
+            }
+            let display = displays.into_iter().nth(placement.display_index).unwrap();
+            let capturer = Capturer::new(display)?;
+            let width = capturer.width();
+            let height = capturer.height();
+            sources.push(DisplaySource { placement: *placement, capturer, width, height });
+        }
+        let width = sources.iter().map(|s| s.placement.x + s.width).max().unwrap_or(0);
+        let height = sources.iter().map(|s| s.placement.y + s.height).max().unwrap_or(0);
+        Ok(Self {
+            sources,
+            buffer: vec![0; width * height * 4],
+            width,
+            height,
+        })
+    }
+
+    // Restituisce il canvas aggiornato, oppure WouldBlock se nessun display ha prodotto un nuovo frame
+    pub(crate) fn frame(&mut self) -> std::io::Result<&[u8]> {
+        if self.poll()? {
+            Ok(&self.buffer)
+        } else {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    // Aggiorna il canvas con i display che hanno un nuovo frame; true se almeno uno è cambiato
+    pub(crate) fn poll(&mut self) -> std::io::Result<bool> {
+        let mut updated = false;
+        for source in &mut self.sources {
+            match source.capturer.frame() {
+                Ok(frame) => {
+                    let stride = frame.len() / source.height;
+                    let row_len = source.width * 4;
+                    for y in 0..source.height {
+                        let src = y * stride;
+                        let dst = ((source.placement.y + y) * self.width + source.placement.x) * 4;
+                        self.buffer[dst..dst + row_len].copy_from_slice(&frame[src..src + row_len]);
+                    }
+                    updated = true;
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(updated)
+    }
+
+    pub(crate) fn latest(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+// L'area può provenire da un layout diverso da quello corrente: la limitiamo ai bordi del frame
+fn clamp_area(area: Rect, width: usize, height: usize) -> Option<Rect> {
+    let min_x = area.min.x.clamp(0.0, width as f32);
+    let min_y = area.min.y.clamp(0.0, height as f32);
+    let max_x = area.max.x.clamp(0.0, width as f32);
+    let max_y = area.max.y.clamp(0.0, height as f32);
+    if max_x - min_x < 1.0 || max_y - min_y < 1.0 {
+        return None;
+    }
+    Some(Rect::from_min_max(Pos2::new(min_x, min_y), Pos2::new(max_x, max_y)))
+}
+
 async fn compress_frame_to_jpeg(frame: &[u8], width: usize, height: usize) -> Result<Vec<u8>, Box<dyn Error>> {
     let mut img_buffer: RgbImage = ImageBuffer::new(width as u32, height as u32);
     for (i, pixel) in img_buffer.pixels_mut().enumerate() {
@@ -62,55 +293,201 @@ async fn compress_frame_to_jpeg(frame: &[u8], width: usize, height: usize) -> Re
     Ok(jpeg_data)
 }
 
+pub(crate) fn crop_frame(frame: &[u8], width: usize, height: usize, selected_area: Option<Rect>) -> (Vec<u8>, usize, usize) {
+    if let Some(area) = selected_area.and_then(|area| clamp_area(area, width, height)) {
+        let start_x = area.min.x as usize;
+        let start_y = area.min.y as usize;
+        let end_x = area.max.x as usize;
+        let end_y = area.max.y as usize;
+        let stride = width * 4;
+        let mut cropped_frame = Vec::new();
+        for y in start_y..end_y {
+            let start_index = y * stride + start_x * 4;
+            let end_index = y * stride + end_x * 4;
+            cropped_frame.extend_from_slice(&frame[start_index..end_index]);
+        }
+        (cropped_frame, end_x - start_x, end_y - start_y)
+    } else {
+        (frame.to_vec(), width, height)
+    }
+}
+
 async fn capture_screen(
     sender: &broadcast::Sender<Vec<u8>>,
     stop_signal: Arc<AtomicBool>,
-    selected_area: Option<Rect>,
+    capture_settings: Arc<RwLock<CaptureSettings>>,
     hotkey_state: Arc<HotkeyState>,
-    display_index: usize
 ) -> Result<(), Box<dyn Error>> {
-    let displays = Display::all()?;
-    if display_index >= displays.len() {
-        return Err("Indice del display non valido".into());
-    }
-    let display = displays.into_iter().nth(display_index).unwrap();
-    let mut capturer = Capturer::new(display)?;
+    let (mut placements, mut selected_area) = {
+        let settings = capture_settings.read().unwrap();
+        (settings.placements(), settings.selected_area)
+    };
+    // Il canvas dei display viene aperto solo quando serve: in modalità scena i display sono gestiti dai layer
+    let mut canvas: Option<DisplayCanvas> = None;
+    let mut scene_renderer = SceneRenderer::new();
+    let mut slideshow_renderer = SlideshowRenderer::new();
+    let mut whiteboard_renderer = WhiteboardRenderer::new();
+    let mut was_blanked = false;
+    let mut sent_annotations: Vec<Annotation> = Vec::new();
     let mut last_frame: Option<Vec<u8>> = None;
+    // Ultimo frame dello schermo senza lente, per ridisegnarla quando si sposta a schermo fermo
+    let mut last_screen: Option<(Vec<u8>, usize, usize)> = None;
+    let mut shown_magnifier: Option<Magnifier> = None;
+    let mut shown_burned: Vec<Annotation> = Vec::new();
+    let mut content_tracker = ContentTracker::new();
     while !stop_signal.load(Ordering::SeqCst) && !hotkey_state.terminate.load(Ordering::SeqCst) {
         if hotkey_state.paused.load(Ordering::SeqCst) {
             sleep(Duration::from_millis(100)).await;
             continue;
         }
-        let width = capturer.width();
-        let height = capturer.height();
-        let default_width = width;
-        let default_height = height;
-        match capturer.frame() {
-            Ok(frame) => {
-                println!("Frame catturato con successo, compressione in corso...");
-                let (selected_frame, cropped_width, cropped_height) = if let Some(area) = selected_area {
-                    let start_x = area.min.x as usize;
-                    let start_y = area.min.y as usize;
-                    let end_x = area.max.x as usize;
-                    let end_y = area.max.y as usize;
-                    let stride = frame.len() / height;
-                    let mut cropped_frame = Vec::new();
-                    for y in start_y..end_y {
-                        let start_index = y * stride + start_x * 4;
-                        let end_index = y * stride + end_x * 4;
-                        cropped_frame.extend_from_slice(&frame[start_index..end_index]);
-                    }
-                    (cropped_frame, end_x - start_x, end_y - start_y)
-                } else {
-                    let stride = frame.len() / height;
-                    let mut full_frame = Vec::new();
-                    for y in 0..height {
-                        let start = y * stride;
-                        let end = start + width * 4;
-                        full_frame.extend_from_slice(&frame[start..end]);
+        let (new_placements, new_selected_area, source, scene, slide, whiteboard, annotations, magnifier, burn, track_content) = {
+            let mut settings = capture_settings.write().unwrap();
+            if settings.source == CaptureSource::Slideshow {
+                settings.slideshow.tick();
+            }
+            (
+                settings.placements(),
+                settings.selected_area,
+                settings.source,
+                settings.active_scene().cloned(),
+                settings.slideshow.current_slide().cloned(),
+                (settings.source == CaptureSource::Whiteboard && whiteboard_renderer.changed(&settings.whiteboard))
+                    .then(|| settings.whiteboard.live_page()),
+                settings.annotations.clone(),
+                settings.magnifier.filter(|_| settings.source == CaptureSource::Screen),
+                settings.burn_annotations,
+                settings.content_pages && settings.source == CaptureSource::Screen,
+            )
+        };
+        // Le annotazioni impresse nei frame non vengono inviate anche come overlay, altrimenti il receiver le vedrebbe doppie
+        let (annotations, burned) = if burn { (Vec::new(), annotations) } else { (annotations, Vec::new()) };
+        // I client nuovi o rimasti indietro ricevono l'insieme completo da send_annotation_snapshot
+        for message in AnnotationMessage::diff(&sent_annotations, &annotations) {
+            let message = message.encode();
+            let message_size = (message.len() as u32).to_be_bytes();
+            if let Err(e) = sender.send([&message_size[..], &message[..]].concat()) {
+                eprintln!("Errore nell'invio delle annotazioni: {}", e);
+            }
+        }
+        sent_annotations = annotations;
+        if source != CaptureSource::Slideshow {
+            slideshow_renderer.reset();
+        }
+        if source != CaptureSource::Whiteboard {
+            whiteboard_renderer.reset();
+        }
+        // Le sorgenti statiche non producono nuovi frame: forziamo un nuovo rendering al cambio di blank
+        let blanked = hotkey_state.screen_blanked.load(Ordering::SeqCst);
+        if blanked != was_blanked {
+            slideshow_renderer.reset();
+            scene_renderer.reset();
+            whiteboard_renderer.reset();
+            was_blanked = blanked;
+        }
+        if new_placements != placements {
+            match DisplayCanvas::open(&new_placements) {
+                Ok(new_canvas) => {
+                    println!("Cambio display: {:?} -> {:?}", placements, new_placements);
+                    canvas = Some(new_canvas);
+                    placements = new_placements;
+                    last_frame = None;
+                    content_tracker.reset();
+                }
+                Err(e) => {
+                    eprintln!("Errore nel cambio display: {}", e);
+                    let mut settings = capture_settings.write().unwrap();
+                    settings.display_error = Some(e.to_string());
+                    if placements.len() == 1 && settings.combined_displays.is_empty() {
+                        settings.display_index = placements[0].display_index;
+                    } else {
+                        settings.combined_displays = placements.clone();
                     }
-                    (full_frame, width, height)
-                };
+                }
+            }
+        }
+        if new_selected_area != selected_area {
+            println!("Area di cattura aggiornata: {:?}", new_selected_area);
+            selected_area = new_selected_area;
+            last_frame = None;
+            content_tracker.reset();
+        }
+
+        let default_width;
+        let default_height;
+        let captured = if let Some(scene) = scene {
+            canvas = None;
+            default_width = scene.width as usize;
+            default_height = scene.height as usize;
+            scene_renderer.frame(&scene).map(|frame| (frame, default_width, default_height))
+        } else if source == CaptureSource::Whiteboard {
+            canvas = None;
+            // Senza modifiche alla lavagna non viene copiata né rasterizzata
+            (default_width, default_height) = match &whiteboard {
+                Some(whiteboard) => (whiteboard.width as usize, whiteboard.height as usize),
+                None => whiteboard_renderer.size().unwrap_or((1920, 1080)),
+            };
+            match &whiteboard {
+                Some(whiteboard) => whiteboard_renderer.frame(whiteboard),
+                None => Err(std::io::ErrorKind::WouldBlock.into()),
+            }
+        } else if source == CaptureSource::Slideshow {
+            canvas = None;
+            let captured = slideshow_renderer.frame(slide.as_ref());
+            if let Ok((_, width, height)) = &captured {
+                default_width = *width;
+                default_height = *height;
+            } else {
+                default_width = 1280;
+                default_height = 720;
+            }
+            captured
+        } else {
+            if canvas.is_none() {
+                canvas = Some(DisplayCanvas::open(&placements)?);
+            }
+            let canvas = canvas.as_mut().unwrap();
+            default_width = canvas.width;
+            default_height = canvas.height;
+            match canvas.frame().map(|frame| crop_frame(frame, default_width, default_height, selected_area)) {
+                Ok(captured) => {
+                    last_screen = Some(captured.clone());
+                    Ok(captured)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && (magnifier != shown_magnifier || burned != shown_burned) => {
+                    last_screen.clone().ok_or(e)
+                }
+                Err(e) => Err(e),
+            }
+        };
+        if source != CaptureSource::Screen {
+            last_screen = None;
+        }
+        // Il riconoscimento usa il frame prima di annotazioni e lente, così non reagisce a ciò che disegna il presentatore
+        let content_page = if track_content {
+            let new_frame = captured.as_ref().ok().map(|(frame, width, height)| (&frame[..], *width, *height));
+            content_tracker.update(new_frame)
+        } else {
+            content_tracker.reset();
+            None
+        };
+        {
+            let mut settings = capture_settings.write().unwrap();
+            if settings.content_page != content_page {
+                settings.content_page = content_page;
+            }
+        }
+        let captured = captured.map(|(frame, width, height)| {
+            let mut frame = raster::burn_annotations(frame, width, height, &burned);
+            if let Some(magnifier) = &magnifier {
+                raster::apply_magnifier(&mut frame, width, height, magnifier);
+            }
+            shown_magnifier = magnifier;
+            shown_burned = burned.clone();
+            (frame, width, height)
+        });
+        match captured {
+            Ok((selected_frame, cropped_width, cropped_height)) => {
+                println!("Frame catturato con successo, compressione in corso...");
                 let jpeg_frame = if hotkey_state.screen_blanked.load(Ordering::SeqCst) {
                     let blank_frame = vec![0; cropped_width * cropped_height * 4];
                     compress_frame_to_jpeg(&blank_frame, cropped_width, cropped_height).await?
@@ -155,7 +532,24 @@ async fn capture_screen(
 
 
 
-pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_area: Option<Rect>,display_index: usize,paused: Arc<AtomicBool>, screen_blanked: Arc<AtomicBool>,terminate: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+// Invia al client l'intero insieme delle annotazioni correnti; niente se sono impresse nei frame
+async fn send_annotation_snapshot(socket: &mut TcpStream, capture_settings: &RwLock<CaptureSettings>) -> std::io::Result<()> {
+    let snapshot: Vec<Vec<u8>> = {
+        let settings = capture_settings.read().unwrap();
+        if settings.burn_annotations {
+            Vec::new()
+        } else {
+            AnnotationMessage::full(&settings.annotations).iter().map(AnnotationMessage::encode).collect()
+        }
+    };
+    for message in snapshot {
+        socket.write_all(&(message.len() as u32).to_be_bytes()).await?;
+        socket.write_all(&message).await?;
+    }
+    Ok(())
+}
+
+pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, capture_settings: Arc<RwLock<CaptureSettings>>, paused: Arc<AtomicBool>, screen_blanked: Arc<AtomicBool>,terminate: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind(addr).await?;
     let (tx, _rx) = broadcast::channel::<Vec<u8>>(100);
     let tx = Arc::new(tx);
@@ -164,6 +558,7 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
         paused,
         screen_blanked,
         terminate,
+        capture_settings: Arc::clone(&capture_settings),
     });
 
     let hotkey_state_clone = Arc::clone(&hotkey_state);
@@ -183,6 +578,11 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
                 let stop_signal_client = Arc::clone(&stop_signal_clone);
                 let hotkey_state_client = Arc::clone(&hotkey_state_clone);
                 tokio::spawn(async move {
+                    // Il nuovo receiver riceve subito le annotazioni già presenti
+                    if send_annotation_snapshot(&mut socket, &hotkey_state_client.capture_settings).await.is_err() {
+                        eprintln!("Errore nell'invio delle annotazioni al client {}", addr);
+                        return;
+                    }
                     while !stop_signal_client.load(Ordering::SeqCst) && !hotkey_state_client.terminate.load(Ordering::SeqCst) {
                         match rx.recv().await {
                             Ok(frame) => {
@@ -201,6 +601,11 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
                             Err(e) => {
                                 if e.to_string().contains("lagged") {
                                     eprintln!("Avviso: il canale è in ritardo, salto alcuni frame: {}", e);
+                                    // Tra i messaggi persi possono esserci modifiche alle annotazioni: le reinviamo per intero
+                                    if send_annotation_snapshot(&mut socket, &hotkey_state_client.capture_settings).await.is_err() {
+                                        eprintln!("Errore nell'invio delle annotazioni al client {}", addr);
+                                        break;
+                                    }
                                     continue; // Continue instead of breaking
                                 }
                                 eprintln!("Errore nella ricezione del frame dal canale: {}", e);
@@ -221,7 +626,7 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
         println!("Listener TCP interrotto.");
     });
 
-    capture_screen(&*Arc::clone(&tx), stop_signal, selected_area, Arc::clone(&hotkey_state),display_index).await?;
+    capture_screen(&*Arc::clone(&tx), stop_signal, capture_settings, Arc::clone(&hotkey_state)).await?;
     println!("Caster completamente fermato.");
     hotkey_state.screen_blanked.store(false, Ordering::SeqCst);
     hotkey_state.paused.store(false, Ordering::SeqCst);
@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+// Miniatura in scala di grigi usata per riconoscere il contenuto catturato
+const THUMBNAIL_WIDTH: usize = 32;
+const THUMBNAIL_HEIGHT: usize = 18;
+// Differenza media (0-255) oltre la quale il contenuto è considerato cambiato
+const CHANGE_THRESHOLD: f32 = 12.0;
+// Il nuovo contenuto deve restare stabile per questo tempo, così le transizioni animate non creano pagine
+const SETTLE_TIME: Duration = Duration::from_millis(400);
+const MAX_PAGES: usize = 64;
+
+pub(crate) struct ContentTracker {
+    pages: Vec<(u64, Vec<u8>)>,
+    current: Option<u64>,
+    pending: Option<(Vec<u8>, Instant)>,
+    next_id: u64,
+}
+
+impl ContentTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            current: None,
+            pending: None,
+            next_id: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.pages.clear();
+        self.current = None;
+        self.pending = None;
+    }
+
+    // Aggiorna la pagina corrente con un nuovo frame BGRA, oppure con None se lo schermo non è cambiato
+    pub(crate) fn update(&mut self, frame: Option<(&[u8], usize, usize)>) -> Option<u64> {
+        if let Some((frame, width, height)) = frame {
+            let thumbnail = thumbnail(frame, width, height);
+            let same_as_current = self.current_thumbnail()
+                .is_some_and(|current| difference(current, &thumbnail) < CHANGE_THRESHOLD);
+            if same_as_current {
+                self.pending = None;
+            } else {
+                let still_settling = self.pending.as_ref()
+                    .is_some_and(|(pending, _)| difference(pending, &thumbnail) < CHANGE_THRESHOLD);
+                if !still_settling {
+                    self.pending = Some((thumbnail, Instant::now()));
+                }
+            }
+        }
+        if self.pending.as_ref().is_some_and(|(_, since)| since.elapsed() >= SETTLE_TIME) || self.current.is_none() {
+            if let Some((thumbnail, _)) = self.pending.take() {
+                self.switch_to(thumbnail);
+            }
+        }
+        self.current
+    }
+
+    fn current_thumbnail(&self) -> Option<&Vec<u8>> {
+        let current = self.current?;
+        self.pages.iter().find(|(id, _)| *id == current).map(|(_, thumbnail)| thumbnail)
+    }
+
+    // Torna a una pagina già vista se il contenuto coincide, altrimenti ne crea una nuova
+    fn switch_to(&mut self, thumbnail: Vec<u8>) {
+        if let Some((id, _)) = self.pages.iter().find(|(_, known)| difference(known, &thumbnail) < CHANGE_THRESHOLD) {
+            self.current = Some(*id);
+            return;
+        }
+        if self.pages.len() >= MAX_PAGES {
+            self.pages.remove(0);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pages.push((id, thumbnail));
+        self.current = Some(id);
+    }
+}
+
+fn thumbnail(frame: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+    if width == 0 || height == 0 || frame.len() < width * height * 4 {
+        return vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT];
+    }
+    for cell_y in 0..THUMBNAIL_HEIGHT {
+        for cell_x in 0..THUMBNAIL_WIDTH {
+            // Campiona una griglia 4x4 all'interno di ogni cella invece di leggere tutti i pixel
+            let mut sum = 0u32;
+            for sample_y in 0..4 {
+                for sample_x in 0..4 {
+                    let x = ((cell_x * 4 + sample_x) * width / (THUMBNAIL_WIDTH * 4)).min(width - 1);
+                    let y = ((cell_y * 4 + sample_y) * height / (THUMBNAIL_HEIGHT * 4)).min(height - 1);
+                    let index = (y * width + x) * 4;
+                    let (b, g, r) = (frame[index] as u32, frame[index + 1] as u32, frame[index + 2] as u32);
+                    sum += (r * 299 + g * 587 + b * 114) / 1000;
+                }
+            }
+            thumbnail.push((sum / 16) as u8);
+        }
+    }
+    thumbnail
+}
+
+fn difference(a: &[u8], b: &[u8]) -> f32 {
+    let total: u32 = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as u32).sum();
+    total as f32 / a.len().max(1) as f32
+}
@@ -2,9 +2,20 @@ use std::error::Error;
 use std::env;
 use eframe::egui::ViewportBuilder;
 
+mod annotation;
 mod caster;
 mod receiver;
 mod ui;
+mod scene;
+mod raster;
+mod slideshow;
+mod whiteboard;
+mod svg;
+mod content;
+mod recorder;
+mod mjpeg;
+mod recovery;
+mod replay;
 
 use ui::MyApp;
 
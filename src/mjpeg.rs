@@ -0,0 +1,410 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+// Frequenza nominale dei file AVI: i frame vengono allineati a questa griglia
+const AVI_FRAME_RATE: u32 = 30;
+// Un cluster Matroska viene chiuso dopo questo tempo, entro il limite dei timestamp relativi a 16 bit
+const MKV_CLUSTER_MS: u64 = 5_000;
+// Verso un processo i cluster vengono inviati più spesso, così il lettore non resta indietro
+pub const MKV_STREAM_CLUSTER_MS: u64 = 1_000;
+const MKV_SEEK_HEAD_SPACE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MjpegContainer {
+    Avi,
+    Mkv,
+}
+
+impl MjpegContainer {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MjpegContainer::Avi => "avi",
+            MjpegContainer::Mkv => "mkv",
+        }
+    }
+}
+
+// Scrive i JPEG ricevuti dal caster direttamente nel contenitore, senza decodificarli
+pub enum MjpegWriter {
+    Avi(AviWriter),
+    Mkv(MkvWriter),
+}
+
+impl MjpegWriter {
+    pub fn create(path: &Path, container: MjpegContainer, width: u32, height: u32) -> io::Result<Self> {
+        Ok(match container {
+            MjpegContainer::Avi => MjpegWriter::Avi(AviWriter::create(path, width, height)?),
+            MjpegContainer::Mkv => MjpegWriter::Mkv(MkvWriter::new(BufWriter::new(File::create(path)?), width, height, MKV_CLUSTER_MS)?),
+        })
+    }
+
+    // timestamp: tempo del frame dall'inizio della registrazione
+    pub fn write_frame(&mut self, jpeg: &[u8], timestamp: Duration) -> io::Result<()> {
+        match self {
+            MjpegWriter::Avi(writer) => writer.write_frame(jpeg, timestamp),
+            MjpegWriter::Mkv(writer) => writer.write_frame(jpeg, timestamp),
+        }
+    }
+
+    // Tempo non ancora coperto prima di timestamp. L'AVI riempie ogni slot mancante con un chunk vuoto:
+    // dopo una pausa lunga conviene aprire un nuovo segmento. Il Matroska ha i timestamp e non ne ha bisogno
+    pub fn gap(&self, timestamp: Duration) -> Duration {
+        match self {
+            MjpegWriter::Avi(writer) => writer.gap(timestamp),
+            MjpegWriter::Mkv(_) => Duration::ZERO,
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            MjpegWriter::Avi(writer) => writer.finish(),
+            MjpegWriter::Mkv(writer) => writer.finish(),
+        }
+    }
+}
+
+pub struct AviWriter {
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    // Posizione del fourcc "movi", a cui sono relativi gli offset dell'indice
+    movi_position: u64,
+    position: u64,
+    // (offset, dimensione) di ogni chunk; i chunk vuoti ripetono il frame precedente
+    index: Vec<(u32, u32)>,
+    max_frame_size: u32,
+}
+
+impl AviWriter {
+    fn create(path: &Path, width: u32, height: u32) -> io::Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            width,
+            height,
+            movi_position: 0,
+            position: 0,
+            index: Vec::new(),
+            max_frame_size: 0,
+        };
+        writer.write_headers(0, 0)?;
+        Ok(writer)
+    }
+
+    // Le dimensioni e i contatori vengono riscritti con i valori finali in finish
+    fn write_headers(&mut self, total_size: u32, movi_size: u32) -> io::Result<()> {
+        let frames = self.index.len() as u32;
+        let (width, height) = (self.width, self.height);
+
+        let mut avih = Vec::with_capacity(56);
+        for value in [
+            1_000_000 / AVI_FRAME_RATE, self.max_frame_size * AVI_FRAME_RATE, 0, 0x10,
+            frames, 0, 1, self.max_frame_size, width, height, 0, 0, 0, 0,
+        ] {
+            avih.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut strh = Vec::with_capacity(56);
+        strh.extend_from_slice(b"vidsMJPG");
+        for value in [0u32, 0, 0, 1, AVI_FRAME_RATE, 0, frames, self.max_frame_size, u32::MAX, 0] {
+            strh.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0u16, 0, width as u16, height as u16] {
+            strh.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut strf = Vec::with_capacity(40);
+        strf.extend_from_slice(&40u32.to_le_bytes());
+        strf.extend_from_slice(&(width as i32).to_le_bytes());
+        strf.extend_from_slice(&(height as i32).to_le_bytes());
+        strf.extend_from_slice(&1u16.to_le_bytes());
+        strf.extend_from_slice(&24u16.to_le_bytes());
+        strf.extend_from_slice(b"MJPG");
+        for value in [width * height * 3, 0, 0, 0, 0] {
+            strf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let strl = [riff_chunk(b"strh", &strh), riff_chunk(b"strf", &strf)].concat();
+        let hdrl = [riff_chunk(b"avih", &avih), riff_list(b"strl", &strl)].concat();
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&total_size.to_le_bytes())?;
+        self.file.write_all(b"AVI ")?;
+        self.file.write_all(&riff_list(b"hdrl", &hdrl))?;
+        self.file.write_all(b"LIST")?;
+        self.file.write_all(&movi_size.to_le_bytes())?;
+        self.movi_position = 12 + 8 + hdrl.len() as u64 + 4 + 8;
+        self.file.write_all(b"movi")?;
+        if self.position == 0 {
+            self.position = self.movi_position + 4;
+        }
+        Ok(())
+    }
+
+    fn gap(&self, timestamp: Duration) -> Duration {
+        let Some(last) = self.index.len().checked_sub(1) else {
+            return Duration::ZERO;
+        };
+        timestamp.saturating_sub(Duration::from_secs_f64(last as f64 / AVI_FRAME_RATE as f64))
+    }
+
+    fn write_frame(&mut self, jpeg: &[u8], timestamp: Duration) -> io::Result<()> {
+        let slot = (timestamp.as_secs_f64() * AVI_FRAME_RATE as f64).round() as usize;
+        if slot < self.index.len() {
+            // Il frame cade in uno slot già occupato: l'AVI ha una frequenza fissa
+            return Ok(());
+        }
+        while self.index.len() < slot {
+            self.write_chunk(&[])?;
+        }
+        self.write_chunk(jpeg)
+    }
+
+    fn write_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        let chunk = riff_chunk(b"00dc", data);
+        if self.position + chunk.len() as u64 + (self.index.len() as u64 + 1) * 16 > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "File AVI troppo grande"));
+        }
+        self.index.push(((self.position - self.movi_position) as u32, data.len() as u32));
+        self.max_frame_size = self.max_frame_size.max(data.len() as u32);
+        self.file.write_all(&chunk)?;
+        self.position += chunk.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let mut idx1 = Vec::with_capacity(self.index.len() * 16);
+        for (offset, size) in &self.index {
+            idx1.extend_from_slice(b"00dc");
+            idx1.extend_from_slice(&(if *size > 0 { 0x10u32 } else { 0 }).to_le_bytes());
+            idx1.extend_from_slice(&offset.to_le_bytes());
+            idx1.extend_from_slice(&size.to_le_bytes());
+        }
+        self.file.write_all(&riff_chunk(b"idx1", &idx1))?;
+        let movi_size = (self.position - self.movi_position) as u32;
+        // La dimensione RIFF esclude gli 8 byte di intestazione, compensati dall'intestazione di idx1
+        let total_size = (self.position + idx1.len() as u64) as u32;
+        self.write_headers(total_size, movi_size)?;
+        self.file.flush()
+    }
+}
+
+fn riff_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 9);
+    chunk.extend_from_slice(id);
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    // I chunk RIFF sono allineati a 2 byte
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn riff_list(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    riff_chunk(b"LIST", &[&kind[..], data].concat())
+}
+
+pub struct MkvWriter<W: Write = BufWriter<File>> {
+    file: W,
+    cluster_ms: u64,
+    // Inizio dei dati del Segment, a cui sono relative le posizioni di SeekHead e Cues
+    segment_data: u64,
+    segment_size_position: u64,
+    duration_position: u64,
+    seek_head_position: u64,
+    position: u64,
+    cluster: Vec<u8>,
+    cluster_timestamp: u64,
+    cues: Vec<(u64, u64)>,
+    last_timestamp: u64,
+}
+
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53BB6B;
+const VOID: u32 = 0xEC;
+
+impl<W: Write> MkvWriter<W> {
+    // Scrive su qualsiasi destinazione: se non è un file, gli elementi da completare alla fine restano provvisori
+    pub fn new(mut file: W, width: u32, height: u32, cluster_ms: u64) -> io::Result<Self> {
+        let header = ebml_element(EBML, &[
+            ebml_uint(0x4286, 1),
+            ebml_uint(0x42F7, 1),
+            ebml_uint(0x42F2, 4),
+            ebml_uint(0x42F3, 8),
+            ebml_element(0x4282, b"matroska"),
+            ebml_uint(0x4287, 4),
+            ebml_uint(0x4285, 2),
+        ].concat());
+        file.write_all(&header)?;
+
+        // Dimensione sconosciuta finché la registrazione non termina, così il file resta leggibile anche se interrotto
+        let segment_size_position = header.len() as u64 + 4;
+        file.write_all(&ebml_id(SEGMENT))?;
+        file.write_all(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])?;
+        let segment_data = segment_size_position + 8;
+
+        // Spazio riservato al SeekHead, scritto alla fine quando è nota la posizione dei Cues
+        let seek_head_position = segment_data;
+        file.write_all(&ebml_void(MKV_SEEK_HEAD_SPACE))?;
+
+        let app = format!("progettoRust {}", env!("CARGO_PKG_VERSION"));
+        let info_body = [
+            ebml_uint(TIMESTAMP_SCALE, 1_000_000),
+            ebml_element(MUXING_APP, app.as_bytes()),
+            ebml_element(WRITING_APP, app.as_bytes()),
+        ].concat();
+        let duration = ebml_element(DURATION, &0f64.to_be_bytes());
+        let info = ebml_element(INFO, &[info_body.clone(), duration.clone()].concat());
+        let info_position = seek_head_position + MKV_SEEK_HEAD_SPACE as u64;
+        let duration_position = info_position + (info.len() - duration.len() + 3) as u64;
+        file.write_all(&info)?;
+
+        let video = ebml_element(0xE0, &[ebml_uint(0xB0, width as u64), ebml_uint(0xBA, height as u64)].concat());
+        let track = ebml_element(TRACK_ENTRY, &[
+            ebml_uint(0xD7, 1),
+            ebml_uint(0x73C5, 1),
+            ebml_uint(0x83, 1),
+            ebml_uint(0x9C, 0),
+            ebml_element(0x86, b"V_MJPEG"),
+            video,
+        ].concat());
+        let tracks = ebml_element(TRACKS, &track);
+        file.write_all(&tracks)?;
+
+        Ok(Self {
+            file,
+            cluster_ms,
+            segment_data,
+            segment_size_position,
+            duration_position,
+            seek_head_position,
+            position: info_position + (info.len() + tracks.len()) as u64,
+            cluster: Vec::new(),
+            cluster_timestamp: 0,
+            cues: Vec::new(),
+            last_timestamp: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, jpeg: &[u8], timestamp: Duration) -> io::Result<()> {
+        // I timestamp devono essere crescenti all'interno della traccia
+        let timestamp = (timestamp.as_millis() as u64).max(self.last_timestamp);
+        if !self.cluster.is_empty() && timestamp - self.cluster_timestamp >= self.cluster_ms {
+            self.flush_cluster()?;
+        }
+        if self.cluster.is_empty() {
+            self.cluster_timestamp = timestamp;
+            self.cluster.extend_from_slice(&ebml_uint(CLUSTER_TIMESTAMP, timestamp));
+        }
+        let relative = (timestamp - self.cluster_timestamp) as i16;
+        let mut block = Vec::with_capacity(jpeg.len() + 4);
+        block.push(0x81);
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(0x80);
+        block.extend_from_slice(jpeg);
+        self.cluster.extend_from_slice(&ebml_element(SIMPLE_BLOCK, &block));
+        self.last_timestamp = timestamp;
+        Ok(())
+    }
+
+    // I cluster vengono scritti interi: un'interruzione perde al massimo l'ultimo
+    fn flush_cluster(&mut self) -> io::Result<()> {
+        if self.cluster.is_empty() {
+            return Ok(());
+        }
+        self.cues.push((self.cluster_timestamp, self.position - self.segment_data));
+        let cluster = ebml_element(CLUSTER, &self.cluster);
+        self.file.write_all(&cluster)?;
+        self.position += cluster.len() as u64;
+        self.cluster.clear();
+        Ok(())
+    }
+
+    // Chiusura senza tornare indietro nel file, per le destinazioni non ricercabili come una pipe
+    pub fn close(mut self) -> io::Result<()> {
+        self.flush_cluster()?;
+        self.file.flush()
+    }
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    fn finish(mut self) -> io::Result<()> {
+        self.flush_cluster()?;
+
+        let cue_points: Vec<u8> = self.cues.iter().flat_map(|(time, position)| {
+            let positions = ebml_element(0xB7, &[ebml_uint(0xF7, 1), ebml_uint(0xF1, *position)].concat());
+            ebml_element(0xBB, &[ebml_uint(0xB3, *time), positions].concat())
+        }).collect();
+        let cues_position = self.position - self.segment_data;
+        let cues = ebml_element(CUES, &cue_points);
+        self.file.write_all(&cues)?;
+        self.position += cues.len() as u64;
+
+        let seek = ebml_element(SEEK, &[
+            ebml_element(SEEK_ID, &ebml_id(CUES)),
+            ebml_element(SEEK_POSITION, &cues_position.to_be_bytes()),
+        ].concat());
+        let seek_head = ebml_element(SEEK_HEAD, &seek);
+        self.file.seek(SeekFrom::Start(self.seek_head_position))?;
+        self.file.write_all(&seek_head)?;
+        self.file.write_all(&ebml_void(MKV_SEEK_HEAD_SPACE - seek_head.len()))?;
+
+        let duration = self.last_timestamp as f64;
+        self.file.seek(SeekFrom::Start(self.duration_position))?;
+        self.file.write_all(&duration.to_be_bytes())?;
+
+        let segment_size = self.position - self.segment_data;
+        self.file.seek(SeekFrom::Start(self.segment_size_position))?;
+        self.file.write_all(&[&[0x01][..], &segment_size.to_be_bytes()[1..]].concat())?;
+        self.file.flush()
+    }
+}
+
+fn ebml_id(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+    bytes[skip..].to_vec()
+}
+
+// Dimensione codificata come intero a lunghezza variabile, con il minimo numero di byte
+fn ebml_size(size: u64) -> Vec<u8> {
+    let length = (1..=8).find(|length| size < (1u64 << (7 * length)) - 1).unwrap_or(8);
+    let marked = size | (1u64 << (7 * length));
+    marked.to_be_bytes()[8 - length..].to_vec()
+}
+
+fn ebml_element(id: u32, data: &[u8]) -> Vec<u8> {
+    [ebml_id(id), ebml_size(data.len() as u64), data.to_vec()].concat()
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count().min(7);
+    ebml_element(id, &bytes[skip..])
+}
+
+// Elemento Void che occupa esattamente length byte
+fn ebml_void(length: usize) -> Vec<u8> {
+    let mut void = vec![VOID as u8, 0x80 | (length - 2) as u8];
+    void.resize(length, 0);
+    void
+}
@@ -0,0 +1,404 @@
+use std::sync::OnceLock;
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use eframe::egui::{Color32, FontDefinitions, Pos2, Rect, Vec2};
+use image::{imageops, RgbaImage};
+use crate::annotation::{arrowhead_size, marker_radius, Annotation, Magnifier, SPOTLIGHT_DIM};
+
+// Font usato per il testo disegnato fuori da egui: lo stesso Ubuntu-Light incluso in egui
+pub fn default_font() -> &'static FontArc {
+    static FONT: OnceLock<FontArc> = OnceLock::new();
+    FONT.get_or_init(|| {
+        let definitions = FontDefinitions::default();
+        let data = definitions.font_data.get("Ubuntu-Light")
+            .or_else(|| definitions.font_data.values().next())
+            .expect("Nessun font disponibile");
+        FontArc::try_from_vec(data.font.to_vec()).expect("Font non valido")
+    })
+}
+
+pub fn blend_pixel(img: &mut RgbaImage, x: i32, y: i32, color: Color32, coverage: f32) {
+    if x < 0 || y < 0 || x >= img.width() as i32 || y >= img.height() as i32 {
+        return;
+    }
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let alpha = (a as f32 / 255.0) * coverage.clamp(0.0, 1.0);
+    if alpha <= 0.0 {
+        return;
+    }
+    let dst = img.get_pixel_mut(x as u32, y as u32);
+    let dst_alpha = dst[3] as f32 / 255.0;
+    let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+    for (channel, src) in [r, g, b].into_iter().enumerate() {
+        let value = (src as f32 * alpha + dst[channel] as f32 * dst_alpha * (1.0 - alpha)) / out_alpha.max(f32::EPSILON);
+        dst[channel] = value.round().clamp(0.0, 255.0) as u8;
+    }
+    dst[3] = (out_alpha * 255.0).round() as u8;
+}
+
+// Disegna il testo con l'angolo superiore sinistro in (x, y), come Align2::LEFT_TOP in egui
+pub fn draw_text(img: &mut RgbaImage, x: f32, y: f32, text: &str, size: f32, color: Color32) {
+    let font = default_font();
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let mut caret_y = y + scaled.ascent();
+    for line in text.split('\n') {
+        let mut caret_x = x;
+        let mut last: Option<GlyphId> = None;
+        for ch in line.chars() {
+            let id = scaled.glyph_id(ch);
+            if let Some(prev) = last {
+                caret_x += scaled.kern(prev, id);
+            }
+            let glyph = id.with_scale_and_position(scale, point(caret_x, caret_y));
+            caret_x += scaled.h_advance(id);
+            last = Some(id);
+            if let Some(outlined) = font.outline_glyph(glyph) {
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    blend_pixel(img, bounds.min.x as i32 + gx as i32, bounds.min.y as i32 + gy as i32, color, coverage);
+                });
+            }
+        }
+        caret_y += scaled.height() + scaled.line_gap();
+    }
+}
+
+pub fn text_size(text: &str, size: f32) -> Vec2 {
+    let scaled = default_font().as_scaled(PxScale::from(size));
+    let width = text.split('\n')
+        .map(|line| line.chars().map(|ch| scaled.h_advance(scaled.glyph_id(ch))).sum::<f32>())
+        .fold(0.0, f32::max);
+    let lines = text.split('\n').count() as f32;
+    Vec2::new(width, lines * scaled.height() + (lines - 1.0) * scaled.line_gap())
+}
+
+// Copia src su img in (x, y) scalandolo di `scale`, rispettando la trasparenza
+pub fn overlay_scaled(img: &mut RgbaImage, src: &RgbaImage, x: i64, y: i64, scale: f32) {
+    if (scale - 1.0).abs() < f32::EPSILON {
+        imageops::overlay(img, src, x, y);
+        return;
+    }
+    let width = (src.width() as f32 * scale).round() as u32;
+    let height = (src.height() as f32 * scale).round() as u32;
+    if width == 0 || height == 0 {
+        return;
+    }
+    let resized = imageops::resize(src, width, height, imageops::FilterType::Triangle);
+    imageops::overlay(img, &resized, x, y);
+}
+
+pub fn draw_line(img: &mut RgbaImage, start: Pos2, end: Pos2, width: f32, color: Color32) {
+    let half = width.max(1.0) / 2.0;
+    let min_x = (start.x.min(end.x) - half - 1.0).floor() as i32;
+    let max_x = (start.x.max(end.x) + half + 1.0).ceil() as i32;
+    let min_y = (start.y.min(end.y) - half - 1.0).floor() as i32;
+    let max_y = (start.y.max(end.y) + half + 1.0).ceil() as i32;
+    let dir = end - start;
+    let length_sq = dir.length_sq();
+    for y in min_y.max(0)..=max_y.min(img.height() as i32 - 1) {
+        for x in min_x.max(0)..=max_x.min(img.width() as i32 - 1) {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let t = if length_sq > 0.0 { ((p - start).dot(dir) / length_sq).clamp(0.0, 1.0) } else { 0.0 };
+            let distance = (p - (start + dir * t)).length();
+            let coverage = half + 0.5 - distance;
+            if coverage > 0.0 {
+                blend_pixel(img, x, y, color, coverage);
+            }
+        }
+    }
+}
+
+// Disegna la spezzata come un'unica forma, così i tratti semitrasparenti non si sommano nei punti di giunzione
+pub fn draw_polyline(img: &mut RgbaImage, points: &[Pos2], width: f32, color: Color32) {
+    let Some(first) = points.first() else {
+        return;
+    };
+    if points.len() == 1 {
+        draw_line(img, *first, *first, width, color);
+        return;
+    }
+    let half = width.max(1.0) / 2.0;
+    let bounds = Rect::from_points(points).expand(half + 1.0);
+    for y in (bounds.min.y.floor().max(0.0) as i32)..=(bounds.max.y.ceil() as i32).min(img.height() as i32 - 1) {
+        for x in (bounds.min.x.floor().max(0.0) as i32)..=(bounds.max.x.ceil() as i32).min(img.width() as i32 - 1) {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let distance = points.windows(2)
+                .map(|pair| {
+                    let dir = pair[1] - pair[0];
+                    let length_sq = dir.length_sq();
+                    let t = if length_sq > 0.0 { ((p - pair[0]).dot(dir) / length_sq).clamp(0.0, 1.0) } else { 0.0 };
+                    (p - (pair[0] + dir * t)).length()
+                })
+                .fold(f32::INFINITY, f32::min);
+            let coverage = half + 0.5 - distance;
+            if coverage > 0.0 {
+                blend_pixel(img, x, y, color, coverage);
+            }
+        }
+    }
+}
+
+// Ellisse inscritta in rect: la distanza dal bordo è approssimata con il gradiente dell'equazione implicita
+pub fn draw_ellipse(img: &mut RgbaImage, rect: Rect, width: f32, stroke: Color32, fill: Option<Color32>) {
+    let center = rect.center();
+    let radius = rect.size() / 2.0;
+    if radius.x <= 0.0 || radius.y <= 0.0 {
+        return;
+    }
+    let half = width.max(1.0) / 2.0;
+    let bounds = rect.expand(half + 1.0);
+    for y in (bounds.min.y.floor().max(0.0) as i32)..=(bounds.max.y.ceil() as i32).min(img.height() as i32 - 1) {
+        for x in (bounds.min.x.floor().max(0.0) as i32)..=(bounds.max.x.ceil() as i32).min(img.width() as i32 - 1) {
+            let offset = Pos2::new(x as f32 + 0.5, y as f32 + 0.5) - center;
+            let value = (offset.x / radius.x).powi(2) + (offset.y / radius.y).powi(2) - 1.0;
+            let gradient = 2.0 * ((offset.x / (radius.x * radius.x)).powi(2) + (offset.y / (radius.y * radius.y)).powi(2)).sqrt();
+            let distance = value / gradient.max(f32::EPSILON);
+            if let Some(fill) = fill {
+                let coverage = 0.5 - distance;
+                if coverage > 0.0 {
+                    blend_pixel(img, x, y, fill, coverage);
+                }
+            }
+            let coverage = half + 0.5 - distance.abs();
+            if coverage > 0.0 {
+                blend_pixel(img, x, y, stroke, coverage);
+            }
+        }
+    }
+}
+
+pub fn stroke_rect(img: &mut RgbaImage, rect: Rect, width: f32, color: Color32) {
+    let corners = [rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+    for i in 0..4 {
+        draw_line(img, corners[i], corners[(i + 1) % 4], width, color);
+    }
+}
+
+pub fn fill_rect(img: &mut RgbaImage, rect: Rect, color: Color32) {
+    let min_x = rect.min.x.round().max(0.0) as i32;
+    let min_y = rect.min.y.round().max(0.0) as i32;
+    let max_x = rect.max.x.round().min(img.width() as f32) as i32;
+    let max_y = rect.max.y.round().min(img.height() as f32) as i32;
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            blend_pixel(img, x, y, color, 1.0);
+        }
+    }
+}
+
+pub fn fill_triangle(img: &mut RgbaImage, points: [Pos2; 3], color: Color32) {
+    let [a, b, c] = points;
+    let area = (b - a).x * (c - a).y - (b - a).y * (c - a).x;
+    if area.abs() < f32::EPSILON {
+        return;
+    }
+    let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as i32;
+    let max_x = a.x.max(b.x).max(c.x).ceil().min(img.width() as f32 - 1.0) as i32;
+    let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as i32;
+    let max_y = a.y.max(b.y).max(c.y).ceil().min(img.height() as f32 - 1.0) as i32;
+    let edge = |p: Pos2, q: Pos2, r: Pos2| (q - p).x * (r - p).y - (q - p).y * (r - p).x;
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let w0 = edge(b, c, p) / area;
+            let w1 = edge(c, a, p) / area;
+            let w2 = edge(a, b, p) / area;
+            if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0 {
+                blend_pixel(img, x, y, color, 1.0);
+            }
+        }
+    }
+}
+
+// Stessa geometria di MyApp::draw_arrow, così l'anteprima e il frame trasmesso coincidono
+pub fn draw_arrow(img: &mut RgbaImage, start: Pos2, end: Pos2, width: f32, color: Color32) {
+    let dir = end - start;
+    let length = dir.length();
+    if length < 2.5 * width {
+        return;
+    }
+    let dir_normalized = dir / length;
+    let (arrowhead_length, arrowhead_width) = arrowhead_size(width);
+    let base = end - dir_normalized * arrowhead_length;
+    let perp = Vec2::new(-dir_normalized.y, dir_normalized.x) * (arrowhead_width / 2.0);
+    draw_line(img, start, base, width, color);
+    fill_triangle(img, [end, base + perp, base - perp], color);
+}
+
+// Cerchio pieno con il numero centrato, come i marcatori disegnati da MyApp::paint_annotations
+pub fn draw_marker(img: &mut RgbaImage, center: Pos2, number: u32, font_size: f32, color: Color32) {
+    let radius = marker_radius(font_size);
+    draw_line(img, center, center, radius * 2.0, color);
+    let label = number.to_string();
+    let size = text_size(&label, font_size);
+    draw_text(img, center.x - size.x / 2.0, center.y - size.y / 2.0, &label, font_size, marker_text_color(color));
+}
+
+// Testo nero sui colori chiari, bianco su quelli scuri
+pub fn marker_text_color(background: Color32) -> Color32 {
+    let [r, g, b, _] = background.to_srgba_unmultiplied();
+    if (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000 > 150 {
+        Color32::BLACK
+    } else {
+        Color32::WHITE
+    }
+}
+
+// Annotazioni su un'immagine trasparente, ad esempio per anteprime ed esportazioni
+pub fn render_annotations(annotations: &[Annotation], width: u32, height: u32) -> RgbaImage {
+    let mut img = RgbaImage::new(width, height);
+    for annotation in annotations {
+        draw_annotation(&mut img, annotation);
+    }
+    img
+}
+
+// Imprime le annotazioni su un frame BGRA come quelli prodotti dal caster
+pub fn burn_annotations(frame: Vec<u8>, width: usize, height: usize, annotations: &[Annotation]) -> Vec<u8> {
+    let mut frame = frame;
+    if annotations.is_empty() || frame.len() != width * height * 4 {
+        return frame;
+    }
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    let mut img = RgbaImage::from_raw(width as u32, height as u32, frame).expect("Dimensioni del frame già verificate");
+    for annotation in annotations {
+        draw_annotation(&mut img, annotation);
+    }
+    let mut frame = img.into_raw();
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    frame
+}
+
+pub fn draw_annotation(img: &mut RgbaImage, annotation: &Annotation) {
+    match annotation {
+        Annotation::Rectangle { rect, style } => {
+            if let Some(fill) = style.fill {
+                fill_rect(img, *rect, fill);
+            }
+            stroke_rect(img, *rect, style.stroke_width, style.color);
+        }
+        Annotation::Arrow { start, end, style } => {
+            draw_arrow(img, *start, *end, style.stroke_width, style.color);
+        }
+        Annotation::Text { pos, content, style, .. } => {
+            draw_text(img, pos.x, pos.y, content, style.font_size, style.color);
+        }
+        Annotation::Line { start, end, style } => {
+            draw_line(img, *start, *end, style.stroke_width, style.color);
+        }
+        Annotation::Ellipse { rect, style } => {
+            draw_ellipse(img, *rect, style.stroke_width, style.color, style.fill);
+        }
+        Annotation::Freehand { points, style } => {
+            draw_polyline(img, points, style.stroke_width, style.color);
+        }
+        Annotation::Marker { pos, number, style } => {
+            draw_marker(img, *pos, *number, style.font_size, style.color);
+        }
+        Annotation::Spotlight { rect, ellipse, style } => {
+            draw_spotlight(img, *rect, *ellipse, style.fill.unwrap_or(SPOTLIGHT_DIM));
+        }
+    }
+}
+
+// Oscura l'immagine fuori da rect (o dall'ellisse inscritta), con bordo anti-aliasing
+pub fn draw_spotlight(img: &mut RgbaImage, rect: Rect, ellipse: bool, dim: Color32) {
+    let center = rect.center();
+    let radius = rect.size() / 2.0;
+    let (width, height) = (img.width() as i32, img.height() as i32);
+    // Solo i pixel vicini alla zona illuminata richiedono il calcolo della distanza, gli altri sono oscurati per intero
+    let min_x = ((rect.min.x - 1.0).floor() as i32).clamp(0, width);
+    let min_y = ((rect.min.y - 1.0).floor() as i32).clamp(0, height);
+    let max_x = ((rect.max.x + 1.0).ceil() as i32).clamp(min_x, width);
+    let max_y = ((rect.max.y + 1.0).ceil() as i32).clamp(min_y, height);
+    for y in 0..height {
+        if y < min_y || y >= max_y {
+            dim_span(img, y, 0, width, dim);
+            continue;
+        }
+        dim_span(img, y, 0, min_x, dim);
+        dim_span(img, y, max_x, width, dim);
+        for x in min_x..max_x {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            // Distanza con segno dal bordo della zona illuminata, positiva all'esterno
+            let distance = if ellipse && radius.x > 0.0 && radius.y > 0.0 {
+                let offset = p - center;
+                let value = (offset.x / radius.x).powi(2) + (offset.y / radius.y).powi(2) - 1.0;
+                let gradient = 2.0 * ((offset.x / (radius.x * radius.x)).powi(2) + (offset.y / (radius.y * radius.y)).powi(2)).sqrt();
+                value / gradient.max(f32::EPSILON)
+            } else {
+                let dx = (rect.min.x - p.x).max(p.x - rect.max.x);
+                let dy = (rect.min.y - p.y).max(p.y - rect.max.y);
+                dx.max(dy)
+            };
+            let coverage = distance + 0.5;
+            if coverage > 0.0 {
+                blend_pixel(img, x, y, dim, coverage);
+            }
+        }
+    }
+}
+
+// Sovrappone color a copertura piena ai pixel della riga y da from a to (escluso), direttamente sul buffer
+fn dim_span(img: &mut RgbaImage, y: i32, from: i32, to: i32, color: Color32) {
+    if from >= to {
+        return;
+    }
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let alpha = a as f32 / 255.0;
+    if alpha <= 0.0 {
+        return;
+    }
+    let stride = img.width() as usize * 4;
+    let start = y as usize * stride + from as usize * 4;
+    let end = y as usize * stride + to as usize * 4;
+    for dst in img.as_mut()[start..end].chunks_exact_mut(4) {
+        let dst_alpha = dst[3] as f32 / 255.0;
+        let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+        for (channel, src) in [r, g, b].into_iter().enumerate() {
+            let value = (src as f32 * alpha + dst[channel] as f32 * dst_alpha * (1.0 - alpha)) / out_alpha.max(f32::EPSILON);
+            dst[channel] = value.round().clamp(0.0, 255.0) as u8;
+        }
+        dst[3] = (out_alpha * 255.0).round() as u8;
+    }
+}
+
+// Ingrandisce la zona sotto la lente su un frame a 4 byte per pixel (RGBA o BGRA), con un bordo grigio
+pub fn apply_magnifier(frame: &mut [u8], width: usize, height: usize, magnifier: &Magnifier) {
+    let radius = magnifier.radius.max(1.0);
+    let zoom = magnifier.zoom.max(1.0);
+    let min_x = (magnifier.center.x - radius - 2.0).floor().max(0.0) as usize;
+    let min_y = (magnifier.center.y - radius - 2.0).floor().max(0.0) as usize;
+    let max_x = ((magnifier.center.x + radius + 2.0).ceil().max(0.0) as usize).min(width);
+    let max_y = ((magnifier.center.y + radius + 2.0).ceil().max(0.0) as usize).min(height);
+    if min_x >= max_x || min_y >= max_y {
+        return;
+    }
+    // La lente legge solo pixel entro il suo raggio: basta copiare il riquadro che la contiene
+    let box_width = max_x - min_x;
+    let mut source = Vec::with_capacity(box_width * (max_y - min_y) * 4);
+    for y in min_y..max_y {
+        source.extend_from_slice(&frame[(y * width + min_x) * 4..(y * width + max_x) * 4]);
+    }
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - magnifier.center.to_vec2();
+            let distance = offset.length();
+            let index = (y * width + x) * 4;
+            if distance <= radius {
+                let src = magnifier.center + offset / zoom;
+                let src_x = (src.x.max(0.0) as usize).clamp(min_x, max_x - 1);
+                let src_y = (src.y.max(0.0) as usize).clamp(min_y, max_y - 1);
+                let src_index = ((src_y - min_y) * box_width + (src_x - min_x)) * 4;
+                frame[index..index + 4].copy_from_slice(&source[src_index..src_index + 4]);
+            }
+            if (distance - radius).abs() <= 1.5 {
+                frame[index..index + 4].copy_from_slice(&[200, 200, 200, 255]);
+            }
+        }
+    }
+}
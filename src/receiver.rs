@@ -4,12 +4,23 @@ use image::ImageReader;
 use std::sync::{Arc, atomic::{AtomicBool, Ordering},RwLock};
 use tokio::time::{sleep, Duration, timeout};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use chrono::Local;
-use std::io::{self};
-use image::RgbaImage;
-use std::process::Command;
+use std::io::{self, Cursor, Write};
+use image::{imageops, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use image::codecs::jpeg::JpegEncoder;
+use std::process::Stdio;
+use std::thread;
 use std::time::{ Instant};
+use std::borrow::Cow;
+use crate::annotation::{Annotation, AnnotationMessage, ANNOTATION_TAG};
+use crate::mjpeg::MjpegWriter;
+use crate::replay::ReplayBuffer;
+use crate::recorder::{read_progress, ConversionJobs, FfmpegRecorder, RecordingFormat, RecordingSettings, ResolutionChange};
+
+// Oltre questo intervallo senza frame lo stream è considerato in pausa
+const PAUSE_THRESHOLD: Duration = Duration::from_secs(1);
+const RESUME_GAP: Duration = Duration::from_millis(40);
 
 pub struct ReceiverState {
     pub recording: bool,
@@ -20,10 +31,29 @@ pub struct ReceiverState {
     last_frame_time: Option<Instant>,
     start_time: Option<Instant>,
     paused_duration: Duration,
-    pause_start_time: Option<Instant>,
     pub framerate: f64,
+    // Istante di ogni frame salvato come PNG, per ricostruirne la durata nella conversione
+    frame_timestamps: Vec<Duration>,
     pub is_paused: bool,
     last_frame_received: Option<Instant>,
+    pub settings: RecordingSettings,
+    // Se ffmpeg è disponibile i frame vengono codificati in diretta, altrimenti salvati come PNG
+    streaming: bool,
+    ffmpeg: Option<FfmpegRecorder>,
+    mjpeg: Option<MjpegWriter>,
+    pub dropped_frames: u32,
+    // Indice del file in scrittura, suo istante di inizio nella registrazione e byte scritti finora
+    segment: u32,
+    segment_start: Duration,
+    segment_bytes: u64,
+    // Errore che ha interrotto la registrazione, da mostrare nell'interfaccia
+    pub recording_error: Option<String>,
+    // Finalizzazioni dei segmenti chiusi, mostrate nell'interfaccia
+    pub jobs: ConversionJobs,
+    // Ultimi secondi di stream, salvabili a posteriori
+    pub replay: ReplayBuffer,
+    // Istante simulato mentre si scrivono frame già ricevuti; None segue il tempo reale
+    pub(crate) clock: Option<Instant>,
 }
 
 impl ReceiverState {
@@ -37,10 +67,22 @@ impl ReceiverState {
             last_frame_time: None,
             start_time: None,
             paused_duration: Duration::new(0, 0),
-            pause_start_time: None,
             framerate: 30.0,
+            frame_timestamps: Vec::new(),
             is_paused: false,
             last_frame_received: None,
+            settings: RecordingSettings::load(),
+            streaming: false,
+            ffmpeg: None,
+            mjpeg: None,
+            dropped_frames: 0,
+            segment: 0,
+            segment_start: Duration::new(0, 0),
+            segment_bytes: 0,
+            recording_error: None,
+            jobs: ConversionJobs::default(),
+            replay: ReplayBuffer::default(),
+            clock: None,
         }
     }
     pub(crate) fn reset_parameter(&mut self){
@@ -50,29 +92,71 @@ impl ReceiverState {
         self.frame_height = None;
         self.last_frame_time = None;
         self.paused_duration = Duration::new(0, 0);
-        self.pause_start_time=None;
+        self.frame_timestamps.clear();
+        self.streaming = false;
+        self.ffmpeg = None;
+        self.mjpeg = None;
+        self.dropped_frames = 0;
+        self.segment = 0;
+        self.segment_start = Duration::new(0, 0);
+        self.segment_bytes = 0;
     }
 
-    pub fn start_recording(&mut self) -> io::Result<()> {
+    // streaming è l'esito di RecordingSettings::validate, eseguita prima senza tenere il lock sullo stato
+    pub fn start_recording(&mut self, streaming: bool) -> io::Result<()> {
         if self.recording {
             return Ok(());
         }
-        let timestamp = Local::now().format("%Y%m%d_%H%M%S");
-        self.output_dir = format!("recording_{}", timestamp);
-        fs::create_dir_all(&self.output_dir)?;
-        let frames_dir = Path::new(&self.output_dir).join("frames");
-        fs::create_dir_all(&frames_dir)?;
+        let name = self.settings.recording_name();
+        let root = Path::new(self.settings.output_dir.trim());
+        // Un nome già usato (per esempio un modello senza {time}) riceve un numero progressivo
+        let mut output_dir = root.join(&name);
+        let mut copy = 1;
+        while output_dir.exists() {
+            copy += 1;
+            output_dir = root.join(format!("{}_{}", name, copy));
+        }
+        fs::create_dir_all(&output_dir)?;
+        self.output_dir = output_dir.to_string_lossy().to_string();
+        self.streaming = streaming;
+        if self.saving_png() {
+            println!("FFmpeg non trovato: i frame verranno salvati come PNG");
+        }
 
         self.recording = true;
         self.frame_count = 0;
-        self.start_time = Some(Instant::now());
-        self.last_frame_time = Some(Instant::now());
+        self.segment = 0;
+        self.segment_start = Duration::new(0, 0);
+        self.recording_error = None;
+        self.start_time = Some(self.now());
+        self.last_frame_time = Some(self.now());
+        // Una pausa in corso all'avvio si misura da qui, non dall'ultimo frame ricevuto prima
+        self.last_frame_received = Some(self.now());
         self.paused_duration = Duration::new(0, 0);
         println!("Started recording in: {}", self.output_dir);
         Ok(())
     }
 
-    pub fn save_frame(&mut self, img: &RgbaImage) -> io::Result<()> {
+    // Registrazione MP4 senza ffmpeg: i frame restano come PNG da convertire in seguito
+    pub fn saving_png(&self) -> bool {
+        matches!(self.settings.format, RecordingFormat::Encoded(_)) && !self.streaming
+    }
+
+    // Nome di un file del segmento corrente: il primo segmento non ha suffisso
+    fn segment_path(&self, name: &str) -> PathBuf {
+        let name = if self.segment == 0 {
+            name.to_string()
+        } else {
+            match name.rsplit_once('.') {
+                Some((stem, extension)) => format!("{}_{:03}.{}", stem, self.segment, extension),
+                None => format!("{}_{:03}", name, self.segment),
+            }
+        };
+        Path::new(&self.output_dir).join(name)
+    }
+
+    // jpeg è il frame così come ricevuto dal caster, img la sua versione decodificata
+    pub fn save_frame(&mut self, jpeg: &[u8], img: &RgbaImage) -> io::Result<()> {
         if !self.recording {
             return Ok(());
         }
@@ -85,27 +169,212 @@ impl ReceiverState {
             ));
         }
 
+        let size_changed = self.frame_width.is_some_and(|frame_width| frame_width != width)
+            || self.frame_height.is_some_and(|frame_height| frame_height != height);
+        let segment_duration = self.frame_timestamp().saturating_sub(self.segment_start);
+        if size_changed && self.settings.on_resolution_change == ResolutionChange::NewSegment {
+            println!("Risoluzione cambiata in {}x{}: nuovo segmento", width, height);
+            self.next_segment()?;
+        } else if self.frame_width.is_some() && self.settings.rotation.is_due(segment_duration, self.segment_bytes) {
+            println!("Limite del segmento raggiunto: nuovo segmento");
+            self.next_segment()?;
+        } else if self.mjpeg.as_ref().is_some_and(|writer| writer.gap(segment_duration) > PAUSE_THRESHOLD) {
+            // La pausa non viene riempita di chunk vuoti: il segmento successivo riparte dopo, con il suo orario nell'indice
+            println!("Pausa nello stream: nuovo segmento");
+            self.next_segment()?;
+        }
+
         if self.frame_width.is_none() {
             self.frame_width = Some(width);
             self.frame_height = Some(height);
+            self.add_to_index()?;
         }
 
-        if Some(width) != self.frame_width || Some(height) != self.frame_height {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Frame dimensions changed during recording",
+        let timestamp = self.frame_timestamp().saturating_sub(self.segment_start);
+        let (frame_width, frame_height) = (self.frame_width.unwrap(), self.frame_height.unwrap());
+
+        // Il caster può cambiare area o display durante lo stream: il frame viene adattato alle dimensioni del segmento
+        let resized = (img.dimensions() != (frame_width, frame_height))
+            .then(|| letterbox(img, frame_width, frame_height));
+
+        if let RecordingFormat::Mjpeg(container) = self.settings.format {
+            if self.mjpeg.is_none() {
+                self.mjpeg = Some(MjpegWriter::create(&self.output_name(), container, frame_width, frame_height)?);
+            }
+        } else if self.streaming && self.ffmpeg.is_none() {
+            // ffmpeg viene avviato al primo frame del segmento, quando se ne conoscono le dimensioni
+            match FfmpegRecorder::start(&self.output_name(), frame_width, frame_height, &self.settings) {
+                Ok(recorder) => self.ffmpeg = Some(recorder),
+                Err(e) => {
+                    eprintln!("Avvio di ffmpeg fallito, salvataggio dei frame come PNG: {}", e);
+                    self.streaming = false;
+                }
+            }
+        }
+
+        // Il JPEG ricevuto va ricodificato solo se il frame è stato ridimensionato
+        let jpeg = match &resized {
+            Some(resized) if self.mjpeg.is_some() || self.ffmpeg.is_some() => Cow::Owned(encode_jpeg(resized)?),
+            _ => Cow::Borrowed(jpeg),
+        };
+
+        if let Some(writer) = &mut self.mjpeg {
+            writer.write_frame(&jpeg, timestamp)?;
+            self.segment_bytes += jpeg.len() as u64;
+        } else if let Some(recorder) = &mut self.ffmpeg {
+            recorder.push(jpeg.into_owned(), timestamp, self.clock.is_some())?;
+            self.dropped_frames = recorder.dropped_frames;
+            // ffmpeg scrive il file man mano e ne riporta la dimensione nell'avanzamento
+            self.segment_bytes = recorder.bytes_written();
+        } else {
+            let frames_dir = self.segment_path("frames");
+            if self.frame_timestamps.is_empty() {
+                fs::create_dir_all(&frames_dir)?;
+            }
+            let frame_path = frames_dir.join(format!("frame_{:06}.png", self.frame_timestamps.len()));
+            let mut png = Vec::new();
+            resized.as_ref().unwrap_or(img).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            fs::write(&frame_path, &png)?;
+            self.segment_bytes += png.len() as u64;
+            self.frame_timestamps.push(timestamp);
+        }
+
+        self.frame_count += 1;
+        self.last_frame_time = Some(self.now());
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.unwrap_or_else(Instant::now)
+    }
+
+    // Tempo trascorso dall'inizio della registrazione, senza le pause se richiesto
+    fn frame_timestamp(&self) -> Duration {
+        let now = self.now();
+        let elapsed = self.start_time.map(|start| now.saturating_duration_since(start)).unwrap_or_default();
+        if self.settings.cut_pauses {
+            elapsed.saturating_sub(self.paused_duration)
+        } else {
+            elapsed
+        }
+    }
+
+    // Un intervallo tra due frame più lungo di PAUSE_THRESHOLD è una pausa dello stream
+    pub(crate) fn frame_arrived(&mut self) {
+        let now = self.now();
+        if let Some(last) = self.last_frame_received {
+            // Conta solo la parte della pausa successiva all'avvio della registrazione
+            let gap = now.saturating_duration_since(self.start_time.map_or(last, |start| last.max(start)));
+            if self.recording && gap > PAUSE_THRESHOLD {
+                // Nel video resta un normale intervallo tra l'ultimo frame prima della pausa e il primo dopo
+                self.paused_duration += gap - RESUME_GAP;
+            }
+        }
+        self.last_frame_received = Some(now);
+    }
+
+    // Lista per il demuxer concat di ffmpeg con la durata reale di ogni frame del segmento
+    fn write_frame_list(&self, list_path: &Path, frames_dir: &Path) -> io::Result<()> {
+        let frames_name = frames_dir.file_name().unwrap_or_default().to_string_lossy();
+        let end = self.frame_timestamp().saturating_sub(self.segment_start);
+        let mut list = String::from("ffconcat version 1.0\n");
+        for (index, timestamp) in self.frame_timestamps.iter().enumerate() {
+            let next = self.frame_timestamps.get(index + 1).copied().unwrap_or(end);
+            list.push_str(&format!(
+                "file '{}/frame_{:06}.png'\nduration {:.6}\n",
+                frames_name,
+                index,
+                next.saturating_sub(*timestamp).as_secs_f64()
             ));
         }
+        // L'ultimo file va ripetuto perché ffmpeg ne rispetti la durata
+        if let Some(last) = self.frame_timestamps.len().checked_sub(1) {
+            list.push_str(&format!("file '{}/frame_{:06}.png'\n", frames_name, last));
+        }
+        fs::write(list_path, list)
+    }
 
-        let frame_path = Path::new(&self.output_dir)
-            .join("frames")
-            .join(format!("frame_{:06}.png", self.frame_count));
+    pub fn segment(&self) -> u32 {
+        self.segment
+    }
 
-        img.save(&frame_path)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    // I file video prendono il nome della cartella della registrazione
+    fn output_name(&self) -> PathBuf {
+        let name = Path::new(&self.output_dir).file_name().unwrap_or_default().to_string_lossy();
+        self.segment_path(&format!("{}.{}", name, self.settings.format.extension()))
+    }
 
-        self.frame_count += 1;
-        self.last_frame_time = Some(Instant::now());
+    fn next_segment(&mut self) -> io::Result<()> {
+        self.close_segment()?;
+        self.segment += 1;
+        self.segment_start = self.frame_timestamp();
+        self.segment_bytes = 0;
+        Ok(())
+    }
+
+    // L'indice viene aggiornato all'apertura di ogni segmento, così resta valido anche dopo un'interruzione
+    fn add_to_index(&self) -> io::Result<()> {
+        let index_path = Path::new(&self.output_dir).join("segments.csv");
+        let mut line = String::new();
+        if self.segment == 0 {
+            line.push_str("file,start_seconds,start_time\n");
+        }
+        let output = self.output_name();
+        line.push_str(&format!(
+            "{},{:.3},{}\n",
+            output.file_name().unwrap_or_default().to_string_lossy(),
+            self.segment_start.as_secs_f64(),
+            Local::now().to_rfc3339()
+        ));
+        let mut index = fs::OpenOptions::new().create(true).append(true).open(index_path)?;
+        index.write_all(line.as_bytes())
+    }
+
+    // Completa il file del segmento corrente; le codifiche con ffmpeg proseguono in background
+    fn close_segment(&mut self) -> io::Result<()> {
+        self.frame_width = None;
+        self.frame_height = None;
+
+        // Il file MJPEG è già completo: basta scrivere indice e intestazioni
+        if let Some(writer) = self.mjpeg.take() {
+            writer.finish()?;
+            // Gli errori arrivano al chiamante; il file completato compare comunque tra quelli pronti
+            let id = self.jobs.start(self.output_name());
+            self.jobs.finish(id, &Ok(()));
+            return Ok(());
+        }
+
+        if let Some(recorder) = self.ffmpeg.take() {
+            let jobs = self.jobs.clone();
+            let id = jobs.start(self.output_name());
+            tokio::task::spawn_blocking(move || {
+                let result = recorder.finish(|progress| jobs.set_progress(id, progress));
+                jobs.finish(id, &result);
+            });
+            return Ok(());
+        }
+
+        if self.frame_timestamps.is_empty() {
+            return Ok(());
+        }
+        let frames_dir = self.segment_path("frames");
+        let list_path = self.segment_path("frames.txt");
+        let output_path = self.output_name();
+        self.write_frame_list(&list_path, &frames_dir)?;
+        self.frame_timestamps.clear();
+
+        let jobs = self.jobs.clone();
+        let id = jobs.start(output_path.clone());
+        let settings = self.settings.clone();
+        tokio::task::spawn_blocking(move || {
+            let conversion_result = convert_frames(&list_path, &output_path, &settings, |progress| jobs.set_progress(id, progress));
+
+            if conversion_result.is_ok() {
+                let _ = delete_frames(&frames_dir, &list_path);
+            }
+            jobs.finish(id, &conversion_result);
+        });
         Ok(())
     }
 
@@ -113,7 +382,7 @@ impl ReceiverState {
         if !self.recording {
             return Ok(());
         }
-        println!("Stopping recording. Frames saved: {}", self.frame_count);
+        println!("Stopping recording. Frames saved: {}, dropped: {}", self.frame_count, self.dropped_frames);
 
         if self.frame_count == 0 {
             self.reset_parameter();
@@ -123,13 +392,15 @@ impl ReceiverState {
             ));
         }
 
-        let duration = self.last_frame_time.unwrap().duration_since(self.start_time.unwrap())- self.paused_duration;
-        self.framerate = self.frame_count as f64 / duration.as_secs_f64();
+        let duration = self.last_frame_time.unwrap().saturating_duration_since(self.start_time.unwrap()).saturating_sub(self.paused_duration);
+        self.framerate = if duration.is_zero() { 0.0 } else { self.frame_count as f64 / duration.as_secs_f64() };
         println!("Framerate effettivo: {:.2} fps", self.framerate );
 
         let metadata = format!(
-            "frames: {}\nfps: {:.2}\nwidth: {}\nheight: {}\nstart_time: {}\n",
+            "frames: {}\ndropped_frames: {}\nsegments: {}\nfps: {:.2}\nwidth: {}\nheight: {}\nstart_time: {}\n",
             self.frame_count,
+            self.dropped_frames,
+            self.segment + 1,
             self.framerate,
             self.frame_width.unwrap_or(0),
             self.frame_height.unwrap_or(0),
@@ -138,57 +409,74 @@ impl ReceiverState {
 
         fs::write(Path::new(&self.output_dir).join("metadata.txt"), metadata)?;
 
-        let output_dir = self.output_dir.clone();
-        let framerate = self.framerate;
-        let frame_width = self.frame_width;
-        let frame_height = self.frame_height;
+        let result = self.close_segment();
 
         // Resetta lo stato immediatamente
         self.reset_parameter();
-
-        tokio::spawn(async move {
-            let conversion_result = convert_to_mp4(&output_dir, framerate, frame_width, frame_height);
-
-            if conversion_result.is_ok() {
-                let _ = delete_frames(&output_dir);
-            }
-        });
-
-        Ok(())
+        result
     }
 
 }
-fn delete_frames(output_dir: &str) -> io::Result<()> {
-    fs::remove_dir_all(Path::new(output_dir).join("frames"))?;
+fn encode_jpeg(img: &RgbaImage) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    JpegEncoder::new(&mut data)
+        .encode_image(&DynamicImage::ImageRgba8(img.clone()).to_rgb8())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(data)
+}
+
+// Scala il frame dentro width x height mantenendo le proporzioni, con bande nere dove avanza spazio
+fn letterbox(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let scale = (width as f32 / img.width() as f32).min(height as f32 / img.height() as f32);
+    let scaled_width = ((img.width() as f32 * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((img.height() as f32 * scale).round() as u32).clamp(1, height);
+    let scaled = imageops::resize(img, scaled_width, scaled_height, imageops::FilterType::Triangle);
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    imageops::overlay(
+        &mut canvas,
+        &scaled,
+        ((width - scaled_width) / 2) as i64,
+        ((height - scaled_height) / 2) as i64,
+    );
+    canvas
+}
+pub(crate) fn delete_frames(frames_dir: &Path, list_path: &Path) -> io::Result<()> {
+    fs::remove_dir_all(frames_dir)?;
+    let _ = fs::remove_file(list_path);
     Ok(())
 }
-fn convert_to_mp4(output_dir: &str, framerate: f64, width: Option<u32>, height: Option<u32>) -> io::Result<()> {
-    if width.is_none() || height.is_none() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Dimensioni frame non valide"
-        ));
+// Converte i PNG della lista nel formato delle impostazioni; on_progress riceve la frazione di video già codificata, ricavata dalle durate della lista
+pub(crate) fn convert_frames(list_path: &Path, output_path: &Path, settings: &RecordingSettings, on_progress: impl Fn(f32)) -> io::Result<()> {
+    let total: f64 = fs::read_to_string(list_path)?
+        .lines()
+        .filter_map(|line| line.strip_prefix("duration "))
+        .filter_map(|duration| duration.trim().parse::<f64>().ok())
+        .sum();
+    let mut child = settings.ffmpeg()
+        .args(["-loglevel", "error", "-progress", "pipe:1", "-nostats"])
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(list_path)
+        .args(settings.encoder_args())
+        .arg("-y")
+        .arg(output_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Gli errori vanno letti in parallelo all'avanzamento, altrimenti ffmpeg può bloccarsi sulla pipe piena
+    let stderr = child.stderr.take().map(|mut stderr| thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = io::Read::read_to_string(&mut stderr, &mut errors);
+        errors
+    }));
+    if let Some(stdout) = child.stdout.take() {
+        read_progress(stdout, |time| on_progress((time.as_secs_f64() / total.max(0.001)) as f32), |_| {});
     }
+    let status = child.wait()?;
 
-    let output = Command::new("ffmpeg")
-        .args(&[
-            "-framerate", &format!("{:.2}", framerate),
-            "-i", &format!("{}/frames/frame_%06d.png", output_dir),
-            "-vf", "scale=ceil(iw/2)*2:ceil(ih/2)*2",
-            "-c:v", "libx264",
-            "-pix_fmt", "yuv420p",
-            "-preset", "fast",  // Ottimizzazione per velocità
-            "-crf", "23",
-            "-y",
-            &format!("{}/output.mp4", output_dir)
-        ])
-        .output()?;
-
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    if !status.success() {
+        let errors = stderr.and_then(|stderr| stderr.join().ok()).unwrap_or_default();
+        return Err(io::Error::other(format!("ffmpeg terminato con {}: {}", status, errors.trim())));
     }
 
     Ok(())
@@ -200,6 +488,7 @@ pub struct SharedFrame {
     pub width: usize,
     pub height: usize,
     pub new_frame: bool,
+    pub annotations: Vec<Annotation>,
 }
 
 impl Default for SharedFrame {
@@ -209,6 +498,7 @@ impl Default for SharedFrame {
             width: 0,
             height: 0,
             new_frame: false,
+            annotations: Vec::new(),
         }
     }
 }
@@ -237,7 +527,6 @@ pub async fn receive_frame(
 
     connected_to_caster.store(true, Ordering::SeqCst);
     let read_timeout = Duration::from_secs(2);
-    let mut no_frame_received = false;
 
     while !stop_signal.load(Ordering::SeqCst) {
         let mut size_buf = [0u8; 4];
@@ -249,7 +538,7 @@ pub async fn receive_frame(
 
                 if let Ok(mut state) = receiver_state.write() {
                     state.is_paused = false;
-                    state.last_frame_received = Some(Instant::now());
+                    state.frame_arrived();
                 }
 
                 if frame_size > 10_000_000 {
@@ -260,7 +549,20 @@ pub async fn receive_frame(
                 let mut buffer = vec![0u8; frame_size];
                 stream.read_exact(&mut buffer).await?;
 
-                let img = ImageReader::new(std::io::Cursor::new(buffer))
+                if buffer.first() == Some(&ANNOTATION_TAG) {
+                    // Un messaggio non valido viene scartato senza interrompere lo stream video
+                    match AnnotationMessage::decode(&buffer) {
+                        Ok(message) => {
+                            if let Ok(mut shared) = shared_frame.write() {
+                                message.apply(&mut shared.annotations);
+                            }
+                        }
+                        Err(e) => eprintln!("Messaggio di annotazione scartato: {}", e),
+                    }
+                    continue;
+                }
+
+                let img = ImageReader::new(std::io::Cursor::new(&buffer))
                     .with_guessed_format()
                     .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Errore nel formato dell'immagine: {}", e)))?
                     .decode()
@@ -277,7 +579,14 @@ pub async fn receive_frame(
                 }
 
                 if let Ok(mut receiver_state)=receiver_state.write(){
-                    receiver_state.save_frame(&img)?;
+                    let buffer_length = receiver_state.settings.buffer_length();
+                    receiver_state.replay.push(&buffer, Instant::now(), buffer_length);
+                    // Un errore di registrazione interrompe la registrazione, non la ricezione dello stream
+                    if let Err(e) = receiver_state.save_frame(&buffer, &img) {
+                        eprintln!("Errore di registrazione: {}", e);
+                        let _ = receiver_state.stop_recording();
+                        receiver_state.recording_error = Some(e.to_string());
+                    }
                 }
             }
 
@@ -309,28 +618,9 @@ pub async fn receive_frame(
                     }
                 }
 
-                if !no_frame_received {
-                    if let Ok(mut receiver_state) = receiver_state.write() {
-                        if receiver_state.pause_start_time.is_none() {
-                            receiver_state.pause_start_time = Some(Instant::now()-Duration::from_millis(1975));
-                        }
-                    }
-                    no_frame_received = true;
-                }
                 sleep(Duration::from_millis(100)).await;
             }
         }
-        if no_frame_received {
-            if let Ok(mut receiver_state) = receiver_state.write() {
-                if let Some(pause_start_time) = receiver_state.pause_start_time {
-             receiver_state.paused_duration += pause_start_time.elapsed();
-            receiver_state.pause_start_time = None;
-                }
-                println!("{:?}",receiver_state.paused_duration);
-                no_frame_received = false;
-
-            }
-        }
 
     }
 
@@ -343,6 +633,7 @@ pub async fn receive_frame(
     if let Ok(mut shared) = shared_frame.write() {
         shared.buffer.clear();
         shared.new_frame = false;
+        shared.annotations.clear();
     }
     connected_to_caster.store(false, Ordering::SeqCst);
 
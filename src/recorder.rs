@@ -0,0 +1,607 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use crate::mjpeg::{MjpegContainer, MkvWriter, MKV_STREAM_CLUSTER_MS};
+
+// Frame in attesa di essere scritti su ffmpeg: oltre questo limite i nuovi frame vengono scartati
+// invece di bloccare la ricezione dello stream
+const QUEUE_LENGTH: usize = 8;
+
+// File in cui vengono ricordate le impostazioni di registrazione tra un avvio e l'altro
+const SETTINGS_FILE: &str = "recording_settings.json";
+// Le dimensioni dispari non sono accettate da yuv420p
+const EVEN_SCALE: &str = "scale=ceil(iw/2)*2:ceil(ih/2)*2";
+
+// Contenitori scritti da ffmpeg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VideoContainer {
+    Mp4,
+    Mkv,
+    Webm,
+    Gif,
+}
+
+impl VideoContainer {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoContainer::Mp4 => "mp4",
+            VideoContainer::Mkv => "mkv",
+            VideoContainer::Webm => "webm",
+            VideoContainer::Gif => "gif",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordingFormat {
+    // Codifica tramite ffmpeg con il codec scelto nelle impostazioni
+    Encoded(VideoContainer),
+    // JPEG ricevuti salvati così come sono, senza ffmpeg
+    Mjpeg(MjpegContainer),
+}
+
+impl RecordingFormat {
+    pub const ALL: [RecordingFormat; 6] = [
+        RecordingFormat::Encoded(VideoContainer::Mp4),
+        RecordingFormat::Encoded(VideoContainer::Mkv),
+        RecordingFormat::Encoded(VideoContainer::Webm),
+        RecordingFormat::Encoded(VideoContainer::Gif),
+        RecordingFormat::Mjpeg(MjpegContainer::Avi),
+        RecordingFormat::Mjpeg(MjpegContainer::Mkv),
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RecordingFormat::Encoded(VideoContainer::Mp4) => "MP4 (FFmpeg)",
+            RecordingFormat::Encoded(VideoContainer::Mkv) => "MKV (FFmpeg)",
+            RecordingFormat::Encoded(VideoContainer::Webm) => "WebM (FFmpeg)",
+            RecordingFormat::Encoded(VideoContainer::Gif) => "GIF animata (FFmpeg)",
+            RecordingFormat::Mjpeg(MjpegContainer::Avi) => "AVI (MJPEG, senza ricodifica)",
+            RecordingFormat::Mjpeg(MjpegContainer::Mkv) => "MKV (MJPEG, senza ricodifica)",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingFormat::Encoded(container) => container.extension(),
+            RecordingFormat::Mjpeg(container) => container.extension(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Vp9,
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 3] = [VideoCodec::H264, VideoCodec::H265, VideoCodec::Vp9];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "H.264",
+            VideoCodec::H265 => "H.265 (HEVC)",
+            VideoCodec::Vp9 => "VP9",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+            VideoCodec::Vp9 => "libvpx-vp9",
+        }
+    }
+
+    // Le GIF hanno un proprio formato e ignorano il codec scelto
+    pub fn supported_by(&self, container: VideoContainer) -> bool {
+        match container {
+            VideoContainer::Mp4 => matches!(self, VideoCodec::H264 | VideoCodec::H265),
+            VideoContainer::Webm => *self == VideoCodec::Vp9,
+            VideoContainer::Mkv | VideoContainer::Gif => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    pub const ALL: [Quality; 3] = [Quality::Low, Quality::Medium, Quality::High];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Quality::Low => "Bassa (file piccoli)",
+            Quality::Medium => "Media",
+            Quality::High => "Alta",
+        }
+    }
+}
+
+// Cosa fare quando il caster cambia risoluzione durante la registrazione
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResolutionChange {
+    // Il frame viene scalato nelle dimensioni iniziali, con bande nere se cambiano le proporzioni
+    Letterbox,
+    // Il file corrente viene chiuso e ne inizia uno nuovo con la nuova risoluzione
+    NewSegment,
+}
+
+impl ResolutionChange {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ResolutionChange::Letterbox => "Adatta con bande nere",
+            ResolutionChange::NewSegment => "Nuovo file",
+        }
+    }
+}
+
+// Limiti oltre i quali la registrazione passa a un nuovo file; 0 disattiva il limite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SegmentRotation {
+    pub max_minutes: u32,
+    pub max_megabytes: u32,
+}
+
+impl SegmentRotation {
+    pub fn is_due(&self, duration: Duration, bytes: u64) -> bool {
+        (self.max_minutes > 0 && duration >= Duration::from_secs(self.max_minutes as u64 * 60))
+            || (self.max_megabytes > 0 && bytes >= self.max_megabytes as u64 * 1024 * 1024)
+    }
+}
+
+// Impostazioni di registrazione del receiver, salvate in SETTINGS_FILE
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingSettings {
+    pub output_dir: String,
+    // Nome della cartella e dei file della registrazione: {date} e {time} vengono sostituiti all'avvio
+    pub filename_template: String,
+    pub format: RecordingFormat,
+    pub codec: VideoCodec,
+    pub quality: Quality,
+    // Vuoto o "ffmpeg" usa l'eseguibile trovato nel PATH
+    pub ffmpeg_path: String,
+    // Se attivo le pause dello stream vengono tolte dalla registrazione, altrimenti restano come fermo immagine
+    pub cut_pauses: bool,
+    pub on_resolution_change: ResolutionChange,
+    pub rotation: SegmentRotation,
+    // Secondi di stream tenuti in memoria per il replay istantaneo; 0 lo disattiva
+    pub replay_seconds: u32,
+    // Minuti di diretta che si possono rivedere nel receiver; 0 lo disattiva. Spento di default:
+    // ogni minuto trattenuto occupa memoria con i JPEG ricevuti
+    pub history_minutes: u32,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            output_dir: ".".to_string(),
+            filename_template: "recording_{date}_{time}".to_string(),
+            format: RecordingFormat::Encoded(VideoContainer::Mp4),
+            codec: VideoCodec::H264,
+            quality: Quality::Medium,
+            ffmpeg_path: "ffmpeg".to_string(),
+            cut_pauses: false,
+            on_resolution_change: ResolutionChange::Letterbox,
+            rotation: SegmentRotation::default(),
+            replay_seconds: 60,
+            history_minutes: 0,
+        }
+    }
+}
+
+impl RecordingSettings {
+    // Un file mancante o non valido lascia le impostazioni predefinite
+    pub fn load() -> Self {
+        match fs::read_to_string(SETTINGS_FILE) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Impostazioni di registrazione non valide, uso quelle predefinite: {}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(SETTINGS_FILE, json)
+    }
+
+    // Il buffer dei frame ricevuti serve sia al replay istantaneo sia a rivedere la diretta
+    pub fn buffer_length(&self) -> Duration {
+        Duration::from_secs((self.replay_seconds as u64).max(self.history_minutes as u64 * 60))
+    }
+
+    pub fn recording_name(&self) -> String {
+        let now = Local::now();
+        self.filename_template
+            .trim()
+            .replace("{date}", &now.format("%Y%m%d").to_string())
+            .replace("{time}", &now.format("%H%M%S").to_string())
+    }
+
+    pub fn ffmpeg(&self) -> Command {
+        let path = self.ffmpeg_path.trim();
+        Command::new(if path.is_empty() { "ffmpeg" } else { path })
+    }
+
+    fn custom_ffmpeg(&self) -> bool {
+        !matches!(self.ffmpeg_path.trim(), "" | "ffmpeg")
+    }
+
+    pub fn ffmpeg_available(&self) -> bool {
+        self.ffmpeg()
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+
+    // Il codec vale solo per i formati codificati da ffmpeg; per le registrazioni MJPEG viene usato
+    // quando i PNG di una registrazione interrotta vanno convertiti
+    fn container(&self) -> VideoContainer {
+        match self.format {
+            RecordingFormat::Encoded(container) => container,
+            RecordingFormat::Mjpeg(_) => VideoContainer::Mp4,
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self.container() {
+            VideoContainer::Gif => "gif",
+            _ => self.codec.encoder(),
+        }
+    }
+
+    pub fn conversion_extension(&self) -> &'static str {
+        self.container().extension()
+    }
+
+    // Argomenti di ffmpeg per l'uscita, dopo l'ingresso e prima del nome del file
+    pub fn encoder_args(&self) -> Vec<String> {
+        let args: Vec<&str> = match (self.container(), self.codec) {
+            (VideoContainer::Gif, _) => {
+                let fps = match self.quality {
+                    Quality::Low => 8,
+                    Quality::Medium => 12,
+                    Quality::High => 20,
+                };
+                // La tavolozza generata sull'intero video evita i colori sbiaditi della tavolozza predefinita
+                return vec![
+                    "-vf".to_string(),
+                    format!("fps={},split[a][b];[a]palettegen[p];[b][p]paletteuse", fps),
+                    "-loop".to_string(),
+                    "0".to_string(),
+                ];
+            }
+            (_, VideoCodec::Vp9) => {
+                let crf = match self.quality {
+                    Quality::Low => "40",
+                    Quality::Medium => "32",
+                    Quality::High => "24",
+                };
+                vec!["-vf", EVEN_SCALE, "-vsync", "vfr", "-c:v", "libvpx-vp9", "-pix_fmt", "yuv420p",
+                     "-crf", crf, "-b:v", "0", "-deadline", "realtime", "-cpu-used", "8"]
+            }
+            (_, codec) => {
+                let (crf, preset) = match (codec, self.quality) {
+                    (VideoCodec::H265, Quality::Low) => ("32", "veryfast"),
+                    (VideoCodec::H265, Quality::Medium) => ("28", "fast"),
+                    (VideoCodec::H265, Quality::High) => ("22", "medium"),
+                    (_, Quality::Low) => ("28", "veryfast"),
+                    (_, Quality::Medium) => ("23", "fast"),
+                    (_, Quality::High) => ("18", "medium"),
+                };
+                vec!["-vf", EVEN_SCALE, "-vsync", "vfr", "-c:v", codec.encoder(), "-pix_fmt", "yuv420p",
+                     "-preset", preset, "-crf", crf]
+            }
+        };
+        args.into_iter().map(String::from).collect()
+    }
+
+    // Controlla le impostazioni prima dell'avvio, così gli errori compaiono subito e non a registrazione finita.
+    // Restituisce se i frame verranno codificati da ffmpeg durante la registrazione. Avvia ffmpeg: da non
+    // eseguire nel thread della UI
+    pub fn validate(&self) -> io::Result<bool> {
+        let name = self.recording_name();
+        if name.is_empty() {
+            return Err(invalid_setting("Il nome della registrazione è vuoto"));
+        }
+        if let Some(character) = name.chars().find(|c| "/\\:*?\"<>|".contains(*c) || c.is_control()) {
+            return Err(invalid_setting(format!("Il nome della registrazione contiene un carattere non valido: '{}'", character)));
+        }
+
+        let output_dir = Path::new(self.output_dir.trim());
+        if output_dir.as_os_str().is_empty() {
+            return Err(invalid_setting("Cartella di destinazione non indicata"));
+        }
+        fs::create_dir_all(output_dir)
+            .map_err(|e| invalid_setting(format!("Impossibile creare la cartella {}: {}", output_dir.display(), e)))?;
+        let probe = output_dir.join(".recording_write_test");
+        fs::write(&probe, b"")
+            .map_err(|e| invalid_setting(format!("La cartella {} non è scrivibile: {}", output_dir.display(), e)))?;
+        let _ = fs::remove_file(probe);
+
+        let RecordingFormat::Encoded(container) = self.format else {
+            return Ok(false);
+        };
+        if !self.codec.supported_by(container) {
+            return Err(invalid_setting(format!(
+                "Il codec {} non è supportato dal contenitore {}",
+                self.codec.label(),
+                container.extension().to_uppercase()
+            )));
+        }
+        if !self.ffmpeg_available() {
+            // Senza ffmpeg nel PATH i frame vengono salvati come PNG; un percorso indicato a mano deve invece funzionare
+            if self.custom_ffmpeg() {
+                return Err(invalid_setting(format!("FFmpeg non trovato in {}", self.ffmpeg_path.trim())));
+            }
+            return Ok(false);
+        }
+        let encoders = self.ffmpeg().args(["-hide_banner", "-encoders"]).output()?;
+        let encoder = self.encoder();
+        let listed = String::from_utf8_lossy(&encoders.stdout)
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some(encoder));
+        if !listed {
+            return Err(invalid_setting(format!("FFmpeg non include l'encoder {}", encoder)));
+        }
+        Ok(true)
+    }
+}
+
+fn invalid_setting(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    // Avanzamento tra 0 e 1, se ffmpeg lo ha già comunicato
+    Running(Option<f32>),
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionJob {
+    pub id: usize,
+    pub output: PathBuf,
+    pub status: JobStatus,
+}
+
+// Conversioni e finalizzazioni in background, condivise con l'interfaccia che ne mostra lo stato
+#[derive(Clone, Default)]
+pub struct ConversionJobs(Arc<RwLock<(usize, Vec<ConversionJob>)>>);
+
+impl ConversionJobs {
+    pub fn start(&self, output: PathBuf) -> usize {
+        let mut jobs = self.0.write().unwrap();
+        jobs.0 += 1;
+        let id = jobs.0;
+        jobs.1.push(ConversionJob { id, output, status: JobStatus::Running(None) });
+        id
+    }
+
+    pub fn set_progress(&self, id: usize, progress: f32) {
+        self.set_status(id, JobStatus::Running(Some(progress.clamp(0.0, 1.0))));
+    }
+
+    pub fn finish(&self, id: usize, result: &io::Result<()>) {
+        match result {
+            Ok(()) => self.set_status(id, JobStatus::Done),
+            Err(e) => {
+                eprintln!("Errore nella conversione della registrazione: {}", e);
+                self.set_status(id, JobStatus::Failed(e.to_string()));
+            }
+        }
+    }
+
+    fn set_status(&self, id: usize, status: JobStatus) {
+        if let Some(job) = self.0.write().unwrap().1.iter_mut().find(|job| job.id == id) {
+            job.status = status;
+        }
+    }
+
+    pub fn dismiss(&self, id: usize) {
+        self.0.write().unwrap().1.retain(|job| job.id != id);
+    }
+
+    pub fn list(&self) -> Vec<ConversionJob> {
+        self.0.read().unwrap().1.clone()
+    }
+
+    pub fn running(&self) -> bool {
+        self.0.read().unwrap().1.iter().any(|job| matches!(job.status, JobStatus::Running(_)))
+    }
+}
+
+// Mostra il file nel gestore file del sistema
+pub fn reveal(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut command = Command::new("explorer");
+        command.arg(format!("/select,{}", path.display()));
+        command
+    };
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    };
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let mut command = {
+        let mut command = Command::new("xdg-open");
+        command.arg(path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")));
+        command
+    };
+    command.spawn().map(|_| ())
+}
+
+// Legge l'output di "-progress pipe:1" e riporta il tempo già codificato e i byte già scritti nel file
+pub(crate) fn read_progress(stdout: impl Read, mut on_time: impl FnMut(Duration), mut on_size: impl FnMut(u64)) {
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if let Some(size) = line.strip_prefix("total_size=").and_then(|size| size.trim().parse::<u64>().ok()) {
+            on_size(size);
+        }
+        // out_time_ms è espresso in microsecondi, come out_time_us
+        let value = line.strip_prefix("out_time_us=").or_else(|| line.strip_prefix("out_time_ms="));
+        if let Some(microseconds) = value.and_then(|value| value.trim().parse::<u64>().ok()) {
+            on_time(Duration::from_micros(microseconds));
+        }
+    }
+}
+
+// Processo ffmpeg che codifica i frame man mano che arrivano. I JPEG ricevuti gli vengono passati sullo stdin
+// in un flusso Matroska, che conserva il timestamp di ogni frame e pesa molto meno dei pixel grezzi
+pub struct FfmpegRecorder {
+    sender: Option<SyncSender<(Vec<u8>, Duration)>>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    child: Child,
+    log_path: PathBuf,
+    pub dropped_frames: u32,
+    // Ultimo timestamp inviato e tempo già codificato da ffmpeg, in microsecondi
+    last_timestamp: Duration,
+    encoded: Arc<AtomicU64>,
+    // Dimensione del file prodotto, come riportata da ffmpeg nell'avanzamento
+    written: Arc<AtomicU64>,
+}
+
+impl FfmpegRecorder {
+    pub fn start(output: &Path, width: u32, height: u32, settings: &RecordingSettings) -> io::Result<Self> {
+        let log_path = output.with_extension("log");
+        let log = File::create(&log_path)?;
+        let mut child = settings.ffmpeg()
+            .args([
+                "-loglevel", "error",
+                "-progress", "pipe:1",
+                "-nostats",
+                "-f", "matroska",
+                "-i", "-",
+            ])
+            .args(settings.encoder_args())
+            .arg("-y")
+            .arg(output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(log)
+            .spawn()?;
+
+        let encoded = Arc::new(AtomicU64::new(0));
+        let written = Arc::new(AtomicU64::new(0));
+        if let Some(stdout) = child.stdout.take() {
+            let encoded = Arc::clone(&encoded);
+            let written = Arc::clone(&written);
+            thread::spawn(move || read_progress(
+                stdout,
+                |time| encoded.store(time.as_micros() as u64, Ordering::Relaxed),
+                |size| written.store(size, Ordering::Relaxed),
+            ));
+        }
+
+        let stdin = child.stdin.take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "Stdin di ffmpeg non disponibile"))?;
+        let (sender, receiver) = sync_channel::<(Vec<u8>, Duration)>(QUEUE_LENGTH);
+        let writer = thread::spawn(move || {
+            let mut stream = MkvWriter::new(stdin, width, height, MKV_STREAM_CLUSTER_MS)?;
+            for (jpeg, timestamp) in receiver {
+                stream.write_frame(&jpeg, timestamp)?;
+            }
+            // Chiudendo lo stdin ffmpeg completa il file
+            stream.close()
+        });
+
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+            child,
+            log_path,
+            dropped_frames: 0,
+            last_timestamp: Duration::ZERO,
+            encoded,
+            written,
+        })
+    }
+
+    // jpeg deve avere le dimensioni indicate in start; timestamp è il tempo dall'inizio della registrazione.
+    // Con wait il frame attende posto nella coda invece di essere scartato, per i frame già bufferizzati
+    pub fn push(&mut self, jpeg: Vec<u8>, timestamp: Duration, wait: bool) -> io::Result<()> {
+        let Some(sender) = &self.sender else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Registrazione già terminata"));
+        };
+        let sent = if wait {
+            sender.send((jpeg, timestamp)).map_err(|error| TrySendError::Disconnected(error.0))
+        } else {
+            sender.try_send((jpeg, timestamp))
+        };
+        match sent {
+            Ok(()) => {
+                self.last_timestamp = timestamp;
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                self.dropped_frames += 1;
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.sender = None;
+                let error = self.writer.take()
+                    .and_then(|writer| writer.join().ok())
+                    .and_then(|result| result.err())
+                    .map(|e| e.to_string())
+                    .unwrap_or_default();
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, format!("ffmpeg si è interrotto: {} {}", error, self.log()).trim().to_string()))
+            }
+        }
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    // Svuota la coda e attende che ffmpeg chiuda il file; blocca finché la codifica non è completa
+    pub fn finish(mut self, mut on_progress: impl FnMut(f32)) -> io::Result<()> {
+        self.sender = None;
+        let written = match self.writer.take().map(|writer| writer.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("Thread di scrittura terminato in modo anomalo")),
+            None => Ok(()),
+        };
+        let total = self.last_timestamp.as_micros().max(1) as f32;
+        let status = loop {
+            if let Some(status) = self.child.try_wait()? {
+                break status;
+            }
+            on_progress(self.encoded.load(Ordering::Relaxed) as f32 / total);
+            thread::sleep(Duration::from_millis(200));
+        };
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg terminato con {}: {}", status, self.log())));
+        }
+        written?;
+        let _ = fs::remove_file(&self.log_path);
+        Ok(())
+    }
+
+    fn log(&self) -> String {
+        fs::read_to_string(&self.log_path).unwrap_or_default().trim().to_string()
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::receiver::{convert_frames, delete_frames};
+use crate::recorder::{ConversionJobs, RecordingSettings};
+
+// Durata dell'ultimo frame quando non esiste un frame successivo da cui ricavarla
+const LAST_FRAME_DURATION: Duration = Duration::from_millis(33);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryStatus {
+    Pending,
+    Converting,
+    Done,
+    Failed(String),
+}
+
+// Registrazione interrotta prima della conversione: restano cartelle di frame PNG senza video
+pub struct UnfinishedRecording {
+    pub dir: PathBuf,
+    // Una cartella di frame per ogni segmento ancora da convertire
+    pub segments: Vec<PathBuf>,
+    pub frames: usize,
+    pub status: Arc<RwLock<RecoveryStatus>>,
+}
+
+impl UnfinishedRecording {
+    // Converte i segmenti in background; lo stato viene aggiornato al termine e l'avanzamento compare tra le conversioni
+    pub fn finalize(&self, jobs: &ConversionJobs, settings: &RecordingSettings) {
+        *self.status.write().unwrap() = RecoveryStatus::Converting;
+        let segments = self.segments.clone();
+        let status = Arc::clone(&self.status);
+        let jobs = jobs.clone();
+        let settings = settings.clone();
+        thread::spawn(move || {
+            let result = segments.iter().try_for_each(|frames_dir| {
+                let (list_path, output_path) = segment_files(frames_dir, settings.conversion_extension());
+                let id = jobs.start(output_path.clone());
+                let result = finalize_segment(frames_dir, &list_path, &output_path, &settings, |progress| jobs.set_progress(id, progress));
+                jobs.finish(id, &result);
+                result
+            });
+            *status.write().unwrap() = match result {
+                Ok(()) => RecoveryStatus::Done,
+                Err(e) => RecoveryStatus::Failed(e.to_string()),
+            };
+        });
+    }
+
+    // Elimina solo i frame ancora da convertire e le loro liste: i segmenti già convertiti e l'indice restano.
+    // La cartella viene rimossa solo se non contiene altro
+    pub fn delete(&self) -> io::Result<()> {
+        // Dopo una conversione fallita a metà i segmenti già convertiti non hanno più la cartella dei frame
+        for frames_dir in self.segments.iter().filter(|frames_dir| frames_dir.exists()) {
+            delete_frames(frames_dir, &frame_list_path(frames_dir))?;
+        }
+        let _ = fs::remove_dir(&self.dir);
+        Ok(())
+    }
+}
+
+// Cerca nella cartella le registrazioni con frame PNG non ancora convertiti: con un modello di nome
+// personalizzato le riconosce dall'indice dei segmenti
+pub fn scan(root: &Path) -> Vec<UnfinishedRecording> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut recordings: Vec<UnfinishedRecording> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && (path.join("segments.csv").exists()
+                    || path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("recording_")))
+        })
+        .filter_map(|dir| {
+            let mut segments: Vec<(PathBuf, usize)> = fs::read_dir(&dir).ok()?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir() && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("frames")))
+                .map(|path| {
+                    let frames = frame_files(&path).len();
+                    (path, frames)
+                })
+                .filter(|(_, frames)| *frames > 0)
+                .collect();
+            if segments.is_empty() {
+                return None;
+            }
+            segments.sort();
+            Some(UnfinishedRecording {
+                dir,
+                frames: segments.iter().map(|(_, frames)| frames).sum(),
+                segments: segments.into_iter().map(|(path, _)| path).collect(),
+                status: Arc::new(RwLock::new(RecoveryStatus::Pending)),
+            })
+        })
+        .collect();
+    recordings.sort_by(|a, b| a.dir.cmp(&b.dir));
+    recordings
+}
+
+fn frame_files(frames_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(frames_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+// In "lezione/frames_001" corrispondono "frames_001.txt" e "lezione_001.mp4", come nei segmenti del receiver
+fn segment_files(frames_dir: &Path, extension: &str) -> (PathBuf, PathBuf) {
+    let dir = frames_dir.parent().unwrap_or(Path::new("."));
+    let recording = dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let name = frames_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let suffix = name.strip_prefix("frames").unwrap_or_default();
+    (frame_list_path(frames_dir), dir.join(format!("{}{}.{}", recording, suffix, extension)))
+}
+
+fn frame_list_path(frames_dir: &Path) -> PathBuf {
+    let dir = frames_dir.parent().unwrap_or(Path::new("."));
+    dir.join(format!("{}.txt", frames_dir.file_name().unwrap_or_default().to_string_lossy()))
+}
+
+fn finalize_segment(frames_dir: &Path, list_path: &Path, output_path: &Path, settings: &RecordingSettings, on_progress: impl Fn(f32)) -> io::Result<()> {
+    // La lista viene scritta all'arresto della registrazione: se manca la ricostruiamo dagli orari dei file
+    if !list_path.exists() {
+        write_frame_list_from_files(frames_dir, list_path)?;
+    }
+    convert_frames(list_path, output_path, settings, on_progress)?;
+    delete_frames(frames_dir, list_path)
+}
+
+fn write_frame_list_from_files(frames_dir: &Path, list_path: &Path) -> io::Result<()> {
+    let frames_name = frames_dir.file_name().unwrap_or_default().to_string_lossy();
+    let files = frame_files(frames_dir);
+    let times: Vec<SystemTime> = files.iter()
+        .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()))
+        .collect::<io::Result<_>>()?;
+    let mut list = String::from("ffconcat version 1.0\n");
+    for (index, file) in files.iter().enumerate() {
+        let duration = times.get(index + 1)
+            .and_then(|next| next.duration_since(times[index]).ok())
+            .unwrap_or(LAST_FRAME_DURATION);
+        list.push_str(&format!(
+            "file '{}/{}'\nduration {:.6}\n",
+            frames_name,
+            file.file_name().unwrap_or_default().to_string_lossy(),
+            duration.as_secs_f64()
+        ));
+    }
+    if let Some(last) = files.last() {
+        list.push_str(&format!("file '{}/{}'\n", frames_name, last.file_name().unwrap_or_default().to_string_lossy()));
+    }
+    fs::write(list_path, list)
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::receiver::ReceiverState;
+use crate::recorder::{ConversionJobs, RecordingSettings};
+
+// Limite di memoria del buffer: oltre questo i frame più vecchi vengono scartati anche se rientrano nella durata
+const MAX_REPLAY_BYTES: usize = 300 * 1024 * 1024;
+// Durata dell'ultimo frame salvato se lo stream ne ha inviato uno solo
+const LAST_FRAME_DURATION: Duration = Duration::from_millis(33);
+
+pub type ReplayFrame = (Instant, Arc<Vec<u8>>);
+
+// Ultimi JPEG ricevuti con il loro istante di arrivo, per salvare a posteriori quello che è già passato
+// e per rivedere la diretta. Il caster ripete l'ultimo frame quando lo schermo è fermo: le ripetizioni
+// condividono lo stesso JPEG e non occupano altra memoria
+#[derive(Default)]
+pub struct ReplayBuffer {
+    frames: VecDeque<ReplayFrame>,
+    bytes: usize,
+}
+
+impl ReplayBuffer {
+    // length è la durata da conservare
+    pub fn push(&mut self, jpeg: &[u8], received: Instant, length: Duration) {
+        let frame = match self.frames.back() {
+            Some((_, last)) if last.as_slice() == jpeg => Arc::clone(last),
+            _ => {
+                self.bytes += jpeg.len();
+                Arc::new(jpeg.to_vec())
+            }
+        };
+        self.frames.push_back((received, frame));
+        while let Some((oldest, _)) = self.frames.front() {
+            let expired = received.saturating_duration_since(*oldest) > length;
+            if !expired && self.bytes <= MAX_REPLAY_BYTES {
+                break;
+            }
+            if let Some((_, jpeg)) = self.frames.pop_front() {
+                if !self.frames.front().is_some_and(|(_, next)| Arc::ptr_eq(next, &jpeg)) {
+                    self.bytes -= jpeg.len();
+                }
+            }
+        }
+    }
+
+    pub fn first_received(&self) -> Option<Instant> {
+        self.frames.front().map(|(received, _)| *received)
+    }
+
+    pub fn last_received(&self) -> Option<Instant> {
+        self.frames.back().map(|(received, _)| *received)
+    }
+
+    pub fn duration(&self) -> Duration {
+        match (self.first_received(), self.last_received()) {
+            (Some(first), Some(last)) => last.saturating_duration_since(first),
+            _ => Duration::ZERO,
+        }
+    }
+
+    // Il frame visibile in un istante: l'ultimo arrivato non dopo at
+    pub fn frame_at(&self, at: Instant) -> Option<ReplayFrame> {
+        let index = self.frames.partition_point(|(received, _)| *received <= at);
+        self.frames.get(index.saturating_sub(1)).cloned()
+    }
+
+    // Frame degli ultimi length secondi; i JPEG sono condivisi, la copia non li duplica
+    pub fn snapshot(&self, length: Duration) -> Vec<ReplayFrame> {
+        let Some(last) = self.last_received() else {
+            return Vec::new();
+        };
+        self.frames.iter()
+            .filter(|(received, _)| last.saturating_duration_since(*received) <= length)
+            .cloned()
+            .collect()
+    }
+}
+
+// Scrive i frame con la normale pipeline di registrazione, rispettando i loro istanti di arrivo
+pub fn write_replay(frames: &[ReplayFrame], settings: &RecordingSettings, jobs: &ConversionJobs) -> io::Result<()> {
+    let (Some((first, _)), Some((last, _))) = (frames.first(), frames.last()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Nessun frame da salvare"));
+    };
+    let mut state = ReceiverState::new();
+    state.settings = settings.clone();
+    state.settings.filename_template = format!("{}_replay", settings.filename_template.trim());
+    state.jobs = jobs.clone();
+    state.clock = Some(*first);
+    let streaming = state.settings.validate()?;
+    state.start_recording(streaming)?;
+
+    for (received, jpeg) in frames {
+        state.clock = Some(*received);
+        state.frame_arrived();
+        let result = image::load_from_memory(jpeg)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .and_then(|img| state.save_frame(jpeg, &img.to_rgba8()));
+        if let Err(e) = result {
+            let _ = state.stop_recording();
+            return Err(e);
+        }
+    }
+
+    // L'ultimo frame dura quanto l'intervallo medio tra i frame
+    let last_frame = match frames.len() {
+        1 => LAST_FRAME_DURATION,
+        count => last.saturating_duration_since(*first) / (count as u32 - 1),
+    };
+    state.clock = Some(*last + last_frame);
+    state.stop_recording()
+}
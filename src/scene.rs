@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io;
+use chrono::Local;
+use eframe::egui::{Color32, Pos2, Rect};
+use image::{Rgba, RgbaImage};
+use crate::caster::{DisplayCanvas, DisplayPlacement};
+use crate::raster;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerSource {
+    Display {
+        display_index: usize,
+        area: Option<Rect>,
+    },
+    Image {
+        path: String,
+    },
+    Text {
+        content: String,
+        size: f32,
+        color: Color32,
+    },
+    Clock {
+        size: f32,
+        color: Color32,
+    },
+}
+
+impl LayerSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LayerSource::Display { .. } => "Display",
+            LayerSource::Image { .. } => "Immagine",
+            LayerSource::Text { .. } => "Testo",
+            LayerSource::Clock { .. } => "Orologio",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub source: LayerSource,
+    pub position: Pos2,
+    pub scale: f32,
+    pub visible: bool,
+}
+
+impl Layer {
+    pub fn new(name: String, source: LayerSource) -> Self {
+        Self {
+            name,
+            source,
+            position: Pos2::ZERO,
+            scale: 1.0,
+            visible: true,
+        }
+    }
+}
+
+// I layer sono disegnati in ordine: l'ultimo della lista è in primo piano
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<Layer>,
+}
+
+impl Scene {
+    pub fn new(name: String, width: u32, height: u32) -> Self {
+        Self {
+            name,
+            width,
+            height,
+            layers: Vec::new(),
+        }
+    }
+}
+
+pub(crate) struct SceneRenderer {
+    displays: HashMap<usize, DisplayCanvas>,
+    images: HashMap<String, Option<RgbaImage>>,
+    last_scene: Option<Scene>,
+    last_clock: String,
+}
+
+impl SceneRenderer {
+    pub(crate) fn new() -> Self {
+        Self {
+            displays: HashMap::new(),
+            images: HashMap::new(),
+            last_scene: None,
+            last_clock: String::new(),
+        }
+    }
+
+    // Restituisce la scena composta in formato BGRA, oppure WouldBlock se nulla è cambiato dall'ultimo frame
+    pub(crate) fn frame(&mut self, scene: &Scene) -> io::Result<Vec<u8>> {
+        let mut updated = self.last_scene.as_ref() != Some(scene);
+
+        let needed: Vec<usize> = scene.layers.iter()
+            .filter(|layer| layer.visible)
+            .filter_map(|layer| match layer.source {
+                LayerSource::Display { display_index, .. } => Some(display_index),
+                _ => None,
+            })
+            .collect();
+        self.displays.retain(|index, _| needed.contains(index));
+        for display_index in needed {
+            if let Entry::Vacant(entry) = self.displays.entry(display_index) {
+                let canvas = DisplayCanvas::open(&[DisplayPlacement { display_index, x: 0, y: 0 }])
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                entry.insert(canvas);
+                updated = true;
+            }
+        }
+        for canvas in self.displays.values_mut() {
+            if canvas.poll()? {
+                updated = true;
+            }
+        }
+
+        let has_clock = scene.layers.iter().any(|layer| layer.visible && matches!(layer.source, LayerSource::Clock { .. }));
+        let clock = Local::now().format("%H:%M:%S").to_string();
+        if has_clock && clock != self.last_clock {
+            updated = true;
+        }
+        self.last_clock = clock;
+
+        if !updated {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.last_scene = Some(scene.clone());
+
+        let mut canvas = RgbaImage::from_pixel(scene.width, scene.height, Rgba([0, 0, 0, 255]));
+        for layer in scene.layers.iter().filter(|layer| layer.visible) {
+            self.draw_layer(&mut canvas, layer);
+        }
+
+        let mut data = canvas.into_raw();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.last_scene = None;
+    }
+
+    fn draw_layer(&mut self, canvas: &mut RgbaImage, layer: &Layer) {
+        let x = layer.position.x;
+        let y = layer.position.y;
+        match &layer.source {
+            LayerSource::Display { display_index, area } => {
+                if let Some(display) = self.displays.get(display_index) {
+                    if let Some(image) = display_region(display, *area) {
+                        raster::overlay_scaled(canvas, &image, x as i64, y as i64, layer.scale);
+                    }
+                }
+            }
+            LayerSource::Image { path } => {
+                let image = self.images.entry(path.clone()).or_insert_with(|| {
+                    match image::open(path) {
+                        Ok(image) => Some(image.to_rgba8()),
+                        Err(e) => {
+                            eprintln!("Errore nel caricamento dell'immagine {}: {}", path, e);
+                            None
+                        }
+                    }
+                });
+                if let Some(image) = image {
+                    raster::overlay_scaled(canvas, image, x as i64, y as i64, layer.scale);
+                }
+            }
+            LayerSource::Text { content, size, color } => {
+                raster::draw_text(canvas, x, y, content, size * layer.scale, *color);
+            }
+            LayerSource::Clock { size, color } => {
+                raster::draw_text(canvas, x, y, &self.last_clock, size * layer.scale, *color);
+            }
+        }
+    }
+}
+
+fn display_region(display: &DisplayCanvas, area: Option<Rect>) -> Option<RgbaImage> {
+    let (min_x, min_y, max_x, max_y) = match area {
+        Some(area) => (
+            (area.min.x.max(0.0) as usize).min(display.width),
+            (area.min.y.max(0.0) as usize).min(display.height),
+            (area.max.x.max(0.0) as usize).min(display.width),
+            (area.max.y.max(0.0) as usize).min(display.height),
+        ),
+        None => (0, 0, display.width, display.height),
+    };
+    if max_x <= min_x || max_y <= min_y {
+        return None;
+    }
+    let frame = display.latest();
+    let mut data = Vec::with_capacity((max_x - min_x) * (max_y - min_y) * 4);
+    for y in min_y..max_y {
+        let start = (y * display.width + min_x) * 4;
+        let end = (y * display.width + max_x) * 4;
+        for pixel in frame[start..end].chunks_exact(4) {
+            data.extend_from_slice(&[pixel[2], pixel[1], pixel[0], 255]);
+        }
+    }
+    RgbaImage::from_raw((max_x - min_x) as u32, (max_y - min_y) as u32, data)
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub struct Slideshow {
+    pub folder: String,
+    pub slides: Vec<PathBuf>,
+    pub current: usize,
+    pub auto_advance: bool,
+    pub interval_secs: f32,
+    last_change: Instant,
+}
+
+impl Default for Slideshow {
+    fn default() -> Self {
+        Self {
+            folder: String::new(),
+            slides: Vec::new(),
+            current: 0,
+            auto_advance: false,
+            interval_secs: 10.0,
+            last_change: Instant::now(),
+        }
+    }
+}
+
+impl Slideshow {
+    // Carica le immagini PNG/JPEG della cartella, ordinate per nome
+    pub fn load_folder(&mut self) -> io::Result<usize> {
+        let mut slides: Vec<PathBuf> = fs::read_dir(&self.folder)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| matches!(ext.to_lowercase().as_str(), "png" | "jpg" | "jpeg"))
+                    .unwrap_or(false)
+            })
+            .collect();
+        slides.sort();
+        if slides.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Nessuna immagine PNG o JPEG nella cartella"));
+        }
+        self.slides = slides;
+        self.current = 0;
+        self.last_change = Instant::now();
+        Ok(self.slides.len())
+    }
+
+    pub fn next(&mut self) {
+        if self.current + 1 < self.slides.len() {
+            self.current += 1;
+        }
+        self.last_change = Instant::now();
+    }
+
+    pub fn previous(&mut self) {
+        self.current = self.current.saturating_sub(1);
+        self.last_change = Instant::now();
+    }
+
+    pub fn current_slide(&self) -> Option<&PathBuf> {
+        self.slides.get(self.current)
+    }
+
+    // Avanza automaticamente se è trascorso l'intervallo; all'ultima slide riparte dalla prima
+    pub fn tick(&mut self) {
+        if !self.auto_advance || self.slides.is_empty() {
+            return;
+        }
+        if self.last_change.elapsed() >= Duration::from_secs_f32(self.interval_secs.max(1.0)) {
+            self.current = (self.current + 1) % self.slides.len();
+            self.last_change = Instant::now();
+        }
+    }
+}
+
+pub(crate) struct SlideshowRenderer {
+    shown: Option<PathBuf>,
+}
+
+impl SlideshowRenderer {
+    pub(crate) fn new() -> Self {
+        Self { shown: None }
+    }
+
+    // Restituisce la slide corrente in formato BGRA, oppure WouldBlock se è già stata inviata o non ce ne sono
+    pub(crate) fn frame(&mut self, slide: Option<&PathBuf>) -> io::Result<(Vec<u8>, usize, usize)> {
+        let Some(slide) = slide else {
+            return Err(io::ErrorKind::WouldBlock.into());
+        };
+        if self.shown.as_ref() == Some(slide) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.shown = Some(slide.clone());
+        let image = image::open(slide)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Errore nel caricamento di {}: {}", slide.display(), e)))?
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        let mut data = image.into_raw();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        Ok((data, width as usize, height as usize))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.shown = None;
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::Write;
+use eframe::egui::{Color32, Pos2, Rect, Vec2};
+use crate::annotation::{arrowhead_size, marker_radius, Annotation, SPOTLIGHT_DIM};
+use crate::raster::marker_text_color;
+
+// Documento SVG di dimensioni width x height con le annotazioni, in coordinate del frame
+pub fn render(annotations: &[Annotation], width: f32, height: f32) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    );
+    for annotation in annotations {
+        write_annotation(&mut svg, annotation, width, height);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn write_annotation(svg: &mut String, annotation: &Annotation, width: f32, height: f32) {
+    match annotation {
+        Annotation::Rectangle { rect, style } => {
+            let _ = writeln!(
+                svg,
+                r#"  <rect x="{}" y="{}" width="{}" height="{}" {} {}/>"#,
+                rect.min.x, rect.min.y, rect.width(), rect.height(),
+                fill(style.fill),
+                stroke(style.color, style.stroke_width)
+            );
+        }
+        Annotation::Arrow { start, end, style } => {
+            let dir = *end - *start;
+            let length = dir.length();
+            if length < 2.5 * style.stroke_width {
+                return;
+            }
+            let dir = dir / length;
+            let (head_length, head_width) = arrowhead_size(style.stroke_width);
+            let base = *end - dir * head_length;
+            let perp = Vec2::new(-dir.y, dir.x) * (head_width / 2.0);
+            let _ = writeln!(
+                svg,
+                r#"  <line x1="{}" y1="{}" x2="{}" y2="{}" {}/>"#,
+                start.x, start.y, base.x, base.y,
+                stroke(style.color, style.stroke_width)
+            );
+            let _ = writeln!(
+                svg,
+                r#"  <polygon points="{}" {}/>"#,
+                points(&[*end, base + perp, base - perp]),
+                fill(Some(style.color))
+            );
+        }
+        Annotation::Text { pos, content, style } => {
+            let _ = write!(
+                svg,
+                r#"  <text x="{}" y="{}" font-family="sans-serif" font-size="{}" dominant-baseline="hanging" {}>"#,
+                pos.x, pos.y, style.font_size,
+                fill(Some(style.color))
+            );
+            for (index, line) in content.split('\n').enumerate() {
+                let dy = if index == 0 { "0" } else { "1.2em" };
+                let _ = write!(svg, r#"<tspan x="{}" dy="{}">{}</tspan>"#, pos.x, dy, escape(line));
+            }
+            svg.push_str("</text>\n");
+        }
+        Annotation::Line { start, end, style } => {
+            let _ = writeln!(
+                svg,
+                r#"  <line x1="{}" y1="{}" x2="{}" y2="{}" stroke-linecap="round" {}/>"#,
+                start.x, start.y, end.x, end.y,
+                stroke(style.color, style.stroke_width)
+            );
+        }
+        Annotation::Ellipse { rect, style } => {
+            let center = rect.center();
+            let _ = writeln!(
+                svg,
+                r#"  <ellipse cx="{}" cy="{}" rx="{}" ry="{}" {} {}/>"#,
+                center.x, center.y, rect.width() / 2.0, rect.height() / 2.0,
+                fill(style.fill),
+                stroke(style.color, style.stroke_width)
+            );
+        }
+        Annotation::Freehand { points: path, style } => {
+            let _ = writeln!(
+                svg,
+                r#"  <polyline points="{}" fill="none" stroke-linecap="round" stroke-linejoin="round" {}/>"#,
+                points(path),
+                stroke(style.color, style.stroke_width)
+            );
+        }
+        Annotation::Marker { pos, number, style } => {
+            let _ = writeln!(
+                svg,
+                r#"  <circle cx="{}" cy="{}" r="{}" {}/>"#,
+                pos.x, pos.y, marker_radius(style.font_size),
+                fill(Some(style.color))
+            );
+            let _ = writeln!(
+                svg,
+                r#"  <text x="{}" y="{}" font-family="sans-serif" font-size="{}" text-anchor="middle" dominant-baseline="central" {}>{}</text>"#,
+                pos.x, pos.y, style.font_size,
+                fill(Some(marker_text_color(style.color))),
+                number
+            );
+        }
+        Annotation::Spotlight { rect, ellipse, style } => {
+            // Il buco nell'oscuramento è ottenuto con la regola evenodd
+            let hole = if *ellipse {
+                let center = rect.center();
+                let (rx, ry) = (rect.width() / 2.0, rect.height() / 2.0);
+                format!(
+                    "M {} {} A {rx} {ry} 0 1 0 {} {} A {rx} {ry} 0 1 0 {} {} Z",
+                    center.x - rx, center.y, center.x + rx, center.y, center.x - rx, center.y
+                )
+            } else {
+                format!("M {} {} H {} V {} H {} Z", rect.min.x, rect.min.y, rect.max.x, rect.max.y, rect.min.x)
+            };
+            let outer = Rect::from_min_size(Pos2::ZERO, Vec2::new(width, height)).union(*rect);
+            let _ = writeln!(
+                svg,
+                r#"  <path d="M {} {} H {} V {} H {} Z {}" fill-rule="evenodd" {}/>"#,
+                outer.min.x, outer.min.y, outer.max.x, outer.max.y, outer.min.x,
+                hole,
+                fill(Some(style.fill.unwrap_or(SPOTLIGHT_DIM)))
+            );
+        }
+    }
+}
+
+fn color(color: Color32) -> (String, f32) {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    (format!("#{:02x}{:02x}{:02x}", r, g, b), a as f32 / 255.0)
+}
+
+fn fill(fill: Option<Color32>) -> String {
+    match fill {
+        Some(fill) => {
+            let (hex, opacity) = color(fill);
+            format!(r#"fill="{}" fill-opacity="{:.3}""#, hex, opacity)
+        }
+        None => r#"fill="none""#.to_string(),
+    }
+}
+
+fn stroke(stroke: Color32, width: f32) -> String {
+    let (hex, opacity) = color(stroke);
+    format!(r#"stroke="{}" stroke-opacity="{:.3}" stroke-width="{}""#, hex, opacity, width)
+}
+
+fn points(points: &[Pos2]) -> String {
+    points.iter().map(|point| format!("{},{}", point.x, point.y)).collect::<Vec<_>>().join(" ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -1,13 +1,27 @@
 use eframe::{egui, App, Frame};
 use crate::{caster, receiver};
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}, RwLock};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc, RwLock};
 use eframe::egui::{Rect, Pos2, Color32, UiBuilder, Image, Widget, FontId};
+use eframe::emath::RectTransform;
 use tokio::runtime::Runtime;
 use image::{ImageBuffer, Rgba};
-use scrap::{Capturer, Display};
-use std::time::Duration;
+use scrap::Display;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use std::thread;
 use crate::receiver::{ReceiverState, SharedFrame};
+use crate::recorder::{self, JobStatus, Quality, RecordingFormat, RecordingSettings, ResolutionChange, VideoCodec};
+use crate::recovery::{self, RecoveryStatus, UnfinishedRecording};
+use crate::replay;
+use crate::annotation::{arrowhead_size, marker_radius, smooth_path, Annotation, AnnotationCommand, AnnotationHistory, AnnotationSet, AnnotationStyle, Magnifier, SPOTLIGHT_DIM};
+use crate::{raster, svg};
+use crate::raster::marker_text_color;
+use crate::caster::{crop_frame, CaptureSettings, CaptureSource, DisplayArrangement, DisplayCanvas, DisplayPlacement};
+use crate::scene::{Layer, LayerSource, Scene};
+use crate::whiteboard::{GridStyle, Whiteboard};
+use chrono::Local;
+use std::path::Path;
+use std::io;
 
 #[derive(Debug, Clone)]
 enum Modality {
@@ -15,22 +29,61 @@ enum Modality {
     Receiver,
 }
 
-#[derive(PartialEq, Default, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Default, Clone, Copy)]
 enum AnnotationTool {
     #[default]
     None,
+    Select,
     Rectangle,
     Arrow,
     Text,
+    Pen,
+    Highlighter,
+    Line,
+    Ellipse,
+    Marker,
+    Eraser,
+    Laser,
+    Spotlight,
+    Magnifier,
 }
 
+#[derive(Clone, Copy)]
+enum AnnotationFileAction {
+    Save,
+    Load,
+    ExportSvg,
+    ExportPng,
+}
 
-#[derive(PartialEq, Clone)]
-#[allow(dead_code)]
-struct TextAnnotation {
-    pos: Pos2,
-    content: String,
-    is_editing: bool,
+// Durata della scia del puntatore laser e della dissolvenza finale delle annotazioni temporanee
+const LASER_TRAIL_SECS: f32 = 0.7;
+const FADE_OUT_SECS: f32 = 1.0;
+// La scia del laser cambia a ogni ridisegno: verso il caster viene inviata al massimo con questo intervallo
+const LASER_SYNC_INTERVAL: Duration = Duration::from_millis(50);
+// Esc resta riservato all'overlay anche per poco dopo l'uso, finché il tasto non viene rilasciato:
+// il caster lo legge a livello di sistema e lo userebbe per fermarsi
+const ESCAPE_GRACE: Duration = Duration::from_millis(500);
+
+struct FadingAnnotation {
+    annotation: Annotation,
+    created: Instant,
+    lifetime: f32,
+}
+
+impl FadingAnnotation {
+    fn remaining(&self) -> f32 {
+        self.lifetime - self.created.elapsed().as_secs_f32()
+    }
+}
+
+
+// Trascinamento in corso con lo strumento di selezione: handle è None quando si sposta l'intera annotazione
+struct AnnotationDrag {
+    index: usize,
+    handle: Option<usize>,
+    origin: Pos2,
+    before: Annotation,
 }
 
 struct AnnotationState {
@@ -40,6 +93,26 @@ struct AnnotationState {
     annotations: Vec<Annotation>,
     editing_text: Option<String>,
     text_edit_id: Option<egui::Id>,
+    style: AnnotationStyle,
+    current_path: Vec<Pos2>,
+    history: AnnotationHistory,
+    selected: Option<usize>,
+    drag: Option<AnnotationDrag>,
+    // Indice del testo esistente in modifica, None se si sta scrivendo un testo nuovo
+    editing_index: Option<usize>,
+    // Secondi dopo cui le annotazioni create con ciascuno strumento si dissolvono
+    fade_after: HashMap<AnnotationTool, f32>,
+    // Annotazioni temporanee: non entrano nella cronologia e non sono selezionabili
+    fading: Vec<FadingAnnotation>,
+    laser_trail: Vec<(Pos2, Instant)>,
+    spotlight_ellipse: bool,
+    // Raggio in punti dello schermo e fattore di ingrandimento della lente
+    magnifier_radius: f32,
+    magnifier_zoom: f32,
+    file_path: String,
+    // Annotazioni messe da parte per ogni contenuto riconosciuto dal caster, e contenuto mostrato ora
+    content_pages: HashMap<u64, Vec<Annotation>>,
+    content_page: Option<u64>,
 }
 
 impl Default for AnnotationState {
@@ -51,32 +124,125 @@ impl Default for AnnotationState {
             annotations: Vec::new(),
             editing_text: None,
             text_edit_id: None,
+            style: AnnotationStyle::default(),
+            current_path: Vec::new(),
+            history: AnnotationHistory::default(),
+            selected: None,
+            drag: None,
+            editing_index: None,
+            fade_after: HashMap::new(),
+            fading: Vec::new(),
+            laser_trail: Vec::new(),
+            spotlight_ellipse: false,
+            magnifier_radius: 120.0,
+            magnifier_zoom: 2.0,
+            file_path: "annotazioni.json".to_string(),
+            content_pages: HashMap::new(),
+            content_page: None,
         }
     }
 }
 
+// Tutte le modifiche alle annotazioni passano dalla cronologia, così possono essere annullate
+impl AnnotationState {
+    fn add(&mut self, annotation: Annotation) {
+        if let Some(lifetime) = self.fade_after.get(&self.active_tool) {
+            self.fading.push(FadingAnnotation { annotation, created: Instant::now(), lifetime: *lifetime });
+            return;
+        }
+        let index = self.annotations.len();
+        self.history.execute(AnnotationCommand::Add { index, annotation }, &mut self.annotations);
+    }
+
+    fn remove(&mut self, index: usize) {
+        let annotation = self.annotations[index].clone();
+        self.history.execute(AnnotationCommand::Remove { index, annotation }, &mut self.annotations);
+    }
+
+    fn replace(&mut self, index: usize, after: Annotation) {
+        let before = self.annotations[index].clone();
+        if before != after {
+            self.history.execute(AnnotationCommand::Replace { index, before, after }, &mut self.annotations);
+        }
+    }
+
+    fn clear(&mut self) {
+        if !self.annotations.is_empty() {
+            self.set_all(Vec::new());
+        }
+    }
+
+    fn set_all(&mut self, after: Vec<Annotation>) {
+        let before = self.annotations.clone();
+        self.history.execute(AnnotationCommand::SetAll { before, after }, &mut self.annotations);
+        self.selected = None;
+    }
+
+    fn undo(&mut self) {
+        self.history.undo(&mut self.annotations);
+        self.selected = None;
+    }
+
+    fn redo(&mut self) {
+        self.history.redo(&mut self.annotations);
+        self.selected = None;
+    }
+
+    // Interrompe qualsiasi operazione in corso, ad esempio quando cambia la lista delle annotazioni
+    fn reset_interaction(&mut self) {
+        self.start_pos = None;
+        self.editing_text = None;
+        self.editing_index = None;
+        self.text_edit_id = None;
+        self.selected = None;
+        self.drag = None;
+        self.current_path.clear();
+        self.fading.clear();
+        self.laser_trail.clear();
+    }
+
+    // Al cambio di contenuto le annotazioni restano associate alla pagina precedente e si ripristinano quelle della nuova
+    fn show_content_page(&mut self, page: Option<u64>) {
+        if page == self.content_page {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.content_page, page);
+        let (Some(previous), Some(page)) = (previous, page) else {
+            // Riconoscimento avviato o interrotto: le annotazioni correnti restano dove sono
+            if page.is_none() {
+                self.content_pages.clear();
+            }
+            return;
+        };
+        self.reset_interaction();
+        let stashed = std::mem::take(&mut self.annotations);
+        if !stashed.is_empty() {
+            self.content_pages.insert(previous, stashed);
+        }
+        self.annotations = self.content_pages.remove(&page).unwrap_or_default();
+        self.history.clear();
+    }
+
+    fn laser_annotation(&self) -> Option<Annotation> {
+        if self.laser_trail.is_empty() {
+            return None;
+        }
+        Some(Annotation::Freehand {
+            points: self.laser_trail.iter().map(|(pos, _)| *pos).collect(),
+            style: AnnotationStyle { color: Color32::RED, stroke_width: 6.0, ..AnnotationStyle::default() },
+        })
+    }
 
-pub enum Annotation {
-    #[allow(dead_code)]
-    Rectangle {
-        rect: egui::Rect,
-        color: Color32,
-    },
-    #[allow(dead_code)]
-    Arrow {
-        start: Pos2,
-        end: Pos2,
-        color: Color32,
-    },
-    #[allow(dead_code)]
-    Text {
-        pos: Pos2,
-        content: String,
-        is_editing: bool,
-        color: Color32,
-    },
+    // Annotazioni da mostrare ai receiver: quelle temporanee restano opache finché non scadono
+    fn visible_annotations(&self) -> Vec<Annotation> {
+        let mut annotations = self.annotations.clone();
+        annotations.extend(self.fading.iter().map(|fading| fading.annotation.clone()));
+        annotations.extend(self.laser_annotation());
+        annotations
+    }
 }
 
+
 pub struct MyApp {
     mode: Option<Modality>,
     caster_address: String,
@@ -102,7 +268,78 @@ pub struct MyApp {
     screen_blanked: Arc<AtomicBool>,
     terminate: Arc<AtomicBool>,
     connected_to_caster: Arc<AtomicBool>,
+    capture_settings: Arc<RwLock<CaptureSettings>>,
+    combine_displays: bool,
+    combined_displays: Vec<DisplayPlacement>,
+    display_arrangement: DisplayArrangement,
+    edited_scene: usize,
+    // Layer (indice, display) di cui si sta selezionando l'area
+    selecting_layer: Option<(usize, usize)>,
+    whiteboard_visible: bool,
+    whiteboard_annotations: AnnotationState,
+    show_remote_annotations: bool,
+    // Anteprima rasterizzata delle annotazioni impresse nei frame, con la lista da cui è stata generata
+    burn_preview: Option<(Vec<Annotation>, egui::TextureHandle)>,
+    // Registrazioni rimaste da convertire dopo una chiusura inattesa, trovate all'avvio
+    unfinished_recordings: Vec<UnfinishedRecording>,
+    // Copia delle impostazioni di registrazione modificata nella finestra; Some finché la finestra è aperta
+    recording_settings_draft: Option<RecordingSettings>,
+    // Impostazioni cambiate dalla barra di registrazione, salvate quando nessun campo è più in modifica
+    recording_settings_unsaved: bool,
+    // Verifica delle impostazioni in corso in background, con il canale da cui arriva l'esito
+    settings_check: Option<(SettingsCheck, mpsc::Receiver<SettingsCheckOutcome>)>,
+    // Visione differita dello stream ricevuto; None segue la diretta
+    time_shift: Option<TimeShift>,
+    // Ultimo invio delle annotazioni al caster, per limitare quelli della scia del laser
+    laser_synced: Instant,
+    // Chiusura rimandata finché le conversioni non terminano, o confermata comunque dall'utente
+    close_pending: bool,
+    force_close: bool,
+}
+// Motivo della verifica delle impostazioni di registrazione: solo il controllo o l'avvio della registrazione
+#[derive(Clone, Copy, PartialEq)]
+enum SettingsCheck {
+    Verify,
+    Start,
+}
+
+// Impostazioni verificate con l'esito di RecordingSettings::validate
+type SettingsCheckOutcome = (RecordingSettings, io::Result<bool>);
+
+// Posizione nella cronologia del receiver mentre la diretta continua a essere bufferizzata
+struct TimeShift {
+    // Istante di arrivo del frame da cui è ripartita la riproduzione, o del frame fermo in pausa
+    position: Instant,
+    // Momento in cui è ripartita la riproduzione; None se in pausa
+    playing_since: Option<Instant>,
+    // Frame attualmente nella texture, per non decodificarlo a ogni ridisegno
+    shown: Option<Instant>,
+    // Frame in decodifica in background, con il canale da cui arriva l'immagine
+    decoding: Option<(Instant, mpsc::Receiver<Option<egui::ColorImage>>)>,
+}
+
+impl TimeShift {
+    fn new(position: Instant, playing: bool) -> Self {
+        Self { position, playing_since: playing.then(Instant::now), shown: None, decoding: None }
+    }
+
+    fn current(&self) -> Instant {
+        self.playing_since.map(|since| self.position + since.elapsed()).unwrap_or(self.position)
+    }
+
+    fn seek(&mut self, position: Instant) {
+        self.position = position;
+        if self.playing_since.is_some() {
+            self.playing_since = Some(Instant::now());
+        }
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        self.position = self.current();
+        self.playing_since = playing.then(Instant::now);
+    }
 }
+
 #[derive(Clone)]
 #[allow(dead_code)]
 struct DisplayInfo {
@@ -114,6 +351,8 @@ struct DisplayInfo {
 
 impl Default for MyApp {
     fn default() -> Self {
+        let receiver_state = ReceiverState::new();
+        let unfinished_recordings = recovery::scan(Path::new(receiver_state.settings.output_dir.trim()));
         Self {
             mode: None,
             caster_address: String::from(""),
@@ -132,19 +371,152 @@ impl Default for MyApp {
             start_pos_relative: None,
             shared_frame: Arc::new(RwLock::new(SharedFrame::default())),
             stream_texture: None,
-            receiver_state: Arc::new(RwLock::new(ReceiverState::new())),
+            receiver_state: Arc::new(RwLock::new(receiver_state)),
             annotation_state: AnnotationState::default(),
             toolbar_visible: false,
             paused: Arc::new(AtomicBool::new(false)),
             screen_blanked: Arc::new(AtomicBool::new(false)),
             terminate: Arc::new(AtomicBool::new(false)),
-            connected_to_caster: Arc::new(AtomicBool::new(false))
+            connected_to_caster: Arc::new(AtomicBool::new(false)),
+            capture_settings: Arc::new(RwLock::new(CaptureSettings {
+                selected_area: None,
+                display_index: 0,
+                combined_displays: Vec::new(),
+                source: CaptureSource::Screen,
+                scenes: Vec::new(),
+                active_scene: 0,
+                slideshow: Default::default(),
+                whiteboard: Whiteboard::default(),
+                annotations: Vec::new(),
+                magnifier: None,
+                burn_annotations: false,
+                content_pages: false,
+                content_page: None,
+                display_error: None,
+                escape_reserved_until: None,
+            })),
+            combine_displays: false,
+            combined_displays: Vec::new(),
+            display_arrangement: DisplayArrangement::Horizontal,
+            edited_scene: 0,
+            selecting_layer: None,
+            whiteboard_visible: false,
+            whiteboard_annotations: AnnotationState::default(),
+            show_remote_annotations: true,
+            burn_preview: None,
+            unfinished_recordings,
+            recording_settings_draft: None,
+            recording_settings_unsaved: false,
+            settings_check: None,
+            time_shift: None,
+            laser_synced: Instant::now(),
+            close_pending: false,
+            force_close: false,
         }
     }
 }
 
 impl MyApp {
 
+    // Propaga area e display correnti al caster, che li applica senza riavviare lo stream
+    fn update_capture_settings(&self) {
+        if let Ok(mut settings) = self.capture_settings.write() {
+            settings.selected_area = self.selected_area;
+            settings.display_index = self.selected_display_index.unwrap_or(0);
+            settings.combined_displays = if self.combine_displays {
+                self.combined_displays.clone()
+            } else {
+                Vec::new()
+            };
+        }
+    }
+
+    // Un cambio display fallito nel caster viene annullato: la selezione torna ai display ancora trasmessi
+    fn follow_display_errors(&mut self) {
+        let mut settings = self.capture_settings.write().unwrap();
+        let Some(error) = settings.display_error.take() else {
+            return;
+        };
+        self.selected_display_index = Some(settings.display_index);
+        if !settings.combined_displays.is_empty() {
+            self.combined_displays = settings.combined_displays.clone();
+        }
+        drop(settings);
+        self.set_error(format!("Impossibile passare al display selezionato: {}", error));
+    }
+
+    fn current_placements(&self) -> Vec<DisplayPlacement> {
+        if let Some((_, display_index)) = self.selecting_layer {
+            vec![DisplayPlacement { display_index, x: 0, y: 0 }]
+        } else if self.combine_displays {
+            self.combined_displays.clone()
+        } else {
+            self.selected_display_index
+                .map(|display_index| vec![DisplayPlacement { display_index, x: 0, y: 0 }])
+                .unwrap_or_default()
+        }
+    }
+
+    fn combined_display_sizes(&self) -> Vec<(usize, usize, usize)> {
+        self.available_displays.iter()
+            .filter(|d| self.combined_displays.iter().any(|p| p.display_index == d.index))
+            .map(|d| (d.index, d.width, d.height))
+            .collect()
+    }
+
+    fn arrange_combined_displays(&mut self) {
+        let sizes = self.combined_display_sizes();
+        self.combined_displays = caster::arrange_displays(&sizes, self.display_arrangement, &self.combined_displays);
+    }
+
+    fn show_display_layout(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Monitor da combinare:");
+            for display in self.available_displays.clone() {
+                let mut included = self.combined_displays.iter().any(|p| p.display_index == display.index);
+                if ui.checkbox(&mut included, &display.name).changed() {
+                    if included {
+                        self.combined_displays.push(DisplayPlacement { display_index: display.index, x: 0, y: 0 });
+                    } else {
+                        self.combined_displays.retain(|p| p.display_index != display.index);
+                    }
+                    changed = true;
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Disposizione:");
+            egui::ComboBox::from_id_salt("display_arrangement")
+                .selected_text(match self.display_arrangement {
+                    DisplayArrangement::Horizontal => "Affiancati",
+                    DisplayArrangement::Vertical => "Impilati",
+                    DisplayArrangement::Custom => "Personalizzata",
+                })
+                .show_ui(ui, |ui| {
+                    changed |= ui.selectable_value(&mut self.display_arrangement, DisplayArrangement::Horizontal, "Affiancati").changed();
+                    changed |= ui.selectable_value(&mut self.display_arrangement, DisplayArrangement::Vertical, "Impilati").changed();
+                    changed |= ui.selectable_value(&mut self.display_arrangement, DisplayArrangement::Custom, "Personalizzata").changed();
+                });
+        });
+        if self.display_arrangement == DisplayArrangement::Custom {
+            let (max_x, max_y) = caster::layout_limits(&self.combined_display_sizes());
+            for placement in &mut self.combined_displays {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Display {}:", placement.display_index + 1));
+                    changed |= ui.add(egui::DragValue::new(&mut placement.x).range(0..=max_x).prefix("x: ")).changed();
+                    changed |= ui.add(egui::DragValue::new(&mut placement.y).range(0..=max_y).prefix("y: ")).changed();
+                });
+            }
+        }
+        if changed {
+            self.combined_displays.sort_by_key(|p| p.display_index);
+            self.arrange_combined_displays();
+            self.selected_area = None;
+            self.update_capture_settings();
+        }
+    }
+
     fn handle_recording_error(&self, error: String) {
         self.set_error(format!("Errore di registrazione: {}", error));
     }
@@ -219,27 +591,45 @@ impl MyApp {
                             ((clamped_pos.x - image_rect.min.x) / image_rect.width()).clamp(0.0, 1.0),
                             ((clamped_pos.y - image_rect.min.y) / image_rect.height()).clamp(0.0, 1.0)
                         );
-                        if let Some(display_index) = self.selected_display_index {
-                            if let Ok(displays) = Display::all() {
-                                if let Some(display) = displays.get(display_index) {
-                                    let screen_width = display.width() as f32;
-                                    let screen_height = display.height() as f32;
-                                    let min_x = (start_relative.x.min(end_relative.x) * screen_width).round();
-                                    let min_y = (start_relative.y.min(end_relative.y) * screen_height).round();
-                                    let max_x = (start_relative.x.max(end_relative.x) * screen_width).round();
-                                    let max_y = (start_relative.y.max(end_relative.y) * screen_height).round();
-                                    if min_x < max_x && min_y < max_y {
-                                        self.selected_area = Some(Rect::from_min_max(
-                                            Pos2::new(min_x, min_y),
-                                            Pos2::new(max_x, max_y)
-                                        ));
+                        let mut area = None;
+                        if let Some(texture) = &self.screenshot {
+                            let [screen_width, screen_height] = texture.size();
+                            let screen_width = screen_width as f32;
+                            let screen_height = screen_height as f32;
+                            let min_x = (start_relative.x.min(end_relative.x) * screen_width).round();
+                            let min_y = (start_relative.y.min(end_relative.y) * screen_height).round();
+                            let max_x = (start_relative.x.max(end_relative.x) * screen_width).round();
+                            let max_y = (start_relative.y.max(end_relative.y) * screen_height).round();
+                            if min_x < max_x && min_y < max_y {
+                                area = Some(Rect::from_min_max(
+                                    Pos2::new(min_x, min_y),
+                                    Pos2::new(max_x, max_y)
+                                ));
+                            }
+                        }
+
+                        if let Some((layer_index, _)) = self.selecting_layer.take() {
+                            if let Some(area) = area {
+                                if let Ok(mut settings) = self.capture_settings.write() {
+                                    if let Some(layer) = settings.scenes.get_mut(self.edited_scene).and_then(|scene| scene.layers.get_mut(layer_index)) {
+                                        if let LayerSource::Display { area: layer_area, .. } = &mut layer.source {
+                                            *layer_area = Some(area);
+                                        }
                                     }
                                 }
                             }
+                            self.selecting_area = false;
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(false));
+                            self.status_message = format!("Area del layer: {:?}", area);
+                            return;
+                        }
+                        if area.is_some() {
+                            self.selected_area = area;
                         }
 
                         self.selecting_area = false;
                         ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(false));
+                        self.update_capture_settings();
 
                         if let Some(area) = self.selected_area {
                             self.status_message = format!("Area selezionata: {:?}", area);
@@ -248,189 +638,1584 @@ impl MyApp {
                         }
                     }
                 }
-
-                if self.selecting_area && self.start_pos.is_some() {
-                    ctx.request_repaint();
-                }
-            }
-        } else {
-            ctx.output_mut(|o| o.cursor_icon = egui::CursorIcon::Default);
+
+                if self.selecting_area && self.start_pos.is_some() {
+                    ctx.request_repaint();
+                }
+            }
+        } else {
+            ctx.output_mut(|o| o.cursor_icon = egui::CursorIcon::Default);
+        }
+    }
+
+    fn draw_arrow(painter: &egui::Painter, start: egui::Pos2, end: egui::Pos2, color: egui::Color32, width: f32) {
+        let stroke = egui::Stroke::new(width, color);
+
+        let dir = end - start;
+        let length = dir.length();
+        if length < 2.5 * width {
+            return;
+        }
+
+        let dir_normalized = dir / length;
+
+        let (arrowhead_length, arrowhead_width) = arrowhead_size(width);
+        let tip = end;
+        let base = end - dir_normalized * arrowhead_length;
+
+        let perp = egui::Vec2::new(-dir_normalized.y, dir_normalized.x) * (arrowhead_width / 2.0);
+        let left = base + perp;
+        let right = base - perp;
+
+        let line_end = base;
+        painter.line_segment([start, line_end], stroke);
+
+        let points = vec![tip, left, right];
+        painter.add(egui::Shape::convex_polygon(points, color, stroke));
+    }
+
+    fn capture_screenshot(&mut self, ctx: &egui::Context) {
+        let placements = self.current_placements();
+        if placements.is_empty() {
+            self.set_error("Nessun display selezionato".to_string());
+            return;
+        }
+
+        let mut canvas = match DisplayCanvas::open(&placements) {
+            Ok(canvas) => {
+                canvas },
+            Err(e) => {
+                self.set_error(format!("Errore nella creazione del capturer: {}", e));
+                return;
+            }
+        };
+        let width = canvas.width;
+        let height = canvas.height;
+        let frame = loop {
+            match canvas.frame() {
+                Ok(frame) => {
+                    break frame.to_vec(); },
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Errore nella cattura del frame: {}", e);
+                    return;
+                }
+            }
+        };
+        let stride = frame.len() / height;
+
+        let mut img_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(
+           width as u32,
+           height as u32
+        );
+
+        for y in 0..height {
+            for x in 0..width {
+                let idx = y * stride + x * 4;
+                if idx + 3 < frame.len() {
+                    let b = frame[idx];
+                    let g = frame[idx + 1];
+                    let r = frame[idx + 2];
+                    img_buffer.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+                }
+            }
+        }
+
+        let color_image = egui::ColorImage::from_rgba_unmultiplied(
+            [width, height],
+            &img_buffer.into_raw(),
+        );
+
+        self.screenshot = Some(ctx.load_texture(
+            "screenshot",
+            color_image,
+            egui::TextureOptions::LINEAR,
+        ));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(true));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Transparent(false));
+    }
+    fn show_scene_editor(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let capture_settings = self.capture_settings.clone();
+        let mut settings = capture_settings.write().unwrap();
+        let mut select_layer_area = None;
+
+        ui.horizontal(|ui| {
+            ui.label("Scena:");
+            egui::ComboBox::from_id_salt("edited_scene")
+                .selected_text(settings.scenes.get(self.edited_scene).map(|scene| scene.name.as_str()).unwrap_or("Nessuna scena"))
+                .show_ui(ui, |ui| {
+                    for (index, scene) in settings.scenes.iter().enumerate() {
+                        ui.selectable_value(&mut self.edited_scene, index, format!("{}. {}", index + 1, scene.name));
+                    }
+                });
+            if ui.button("➕ Nuova").clicked() {
+                let (width, height) = self.available_displays.first()
+                    .map(|display| (display.width as u32, display.height as u32))
+                    .unwrap_or((1920, 1080));
+                let name = format!("Scena {}", settings.scenes.len() + 1);
+                settings.scenes.push(Scene::new(name, width, height));
+                self.edited_scene = settings.scenes.len() - 1;
+            }
+            if ui.add_enabled(self.edited_scene < settings.scenes.len(), egui::Button::new("🗑 Elimina")).clicked() {
+                settings.scenes.remove(self.edited_scene);
+                self.edited_scene = self.edited_scene.saturating_sub(1);
+                settings.active_scene = settings.active_scene.min(settings.scenes.len().saturating_sub(1));
+            }
+            if self.edited_scene < settings.scenes.len() {
+                if settings.active_scene == self.edited_scene {
+                    ui.label(egui::RichText::new("● IN ONDA").color(Color32::RED).strong());
+                } else if ui.button("▶ Attiva").clicked() {
+                    settings.active_scene = self.edited_scene;
+                }
+            }
+        });
+
+        let available_displays = self.available_displays.clone();
+        let Some(scene) = settings.scenes.get_mut(self.edited_scene) else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label("Nome:");
+            ui.text_edit_singleline(&mut scene.name);
+            ui.label("Dimensioni:");
+            ui.add(egui::DragValue::new(&mut scene.width).range(16..=7680));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut scene.height).range(16..=4320));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Aggiungi layer:");
+            if ui.button("🖥 Display").clicked() {
+                let display_index = self.selected_display_index.unwrap_or(0);
+                scene.layers.push(Layer::new(format!("Display {}", display_index + 1), LayerSource::Display { display_index, area: None }));
+            }
+            if ui.button("🖼 Immagine").clicked() {
+                scene.layers.push(Layer::new("Immagine".to_string(), LayerSource::Image { path: String::new() }));
+            }
+            if ui.button("🔤 Testo").clicked() {
+                scene.layers.push(Layer::new("Testo".to_string(), LayerSource::Text { content: "Testo".to_string(), size: 32.0, color: Color32::WHITE }));
+            }
+            if ui.button("🕒 Orologio").clicked() {
+                scene.layers.push(Layer::new("Orologio".to_string(), LayerSource::Clock { size: 32.0, color: Color32::WHITE }));
+            }
+        });
+
+        let mut move_up = None;
+        let mut move_down = None;
+        let mut remove = None;
+        let layer_count = scene.layers.len();
+        // I layer in cima alla lista sono quelli in primo piano
+        for index in (0..layer_count).rev() {
+            let layer = &mut scene.layers[index];
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut layer.visible, "");
+                ui.label(format!("[{}]", layer.source.label()));
+                ui.text_edit_singleline(&mut layer.name);
+                if ui.add_enabled(index + 1 < layer_count, egui::Button::new("⬆")).on_hover_text("Porta avanti").clicked() {
+                    move_up = Some(index);
+                }
+                if ui.add_enabled(index > 0, egui::Button::new("⬇")).on_hover_text("Porta indietro").clicked() {
+                    move_down = Some(index);
+                }
+                if ui.button("🗑").clicked() {
+                    remove = Some(index);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut layer.position.x).prefix("x: "));
+                ui.add(egui::DragValue::new(&mut layer.position.y).prefix("y: "));
+                ui.add(egui::Slider::new(&mut layer.scale, 0.1..=4.0).text("scala"));
+            });
+            ui.horizontal(|ui| {
+                match &mut layer.source {
+                    LayerSource::Display { display_index, area } => {
+                        egui::ComboBox::from_id_salt(("layer_display", index))
+                            .selected_text(available_displays.get(*display_index).map(|d| d.name.as_str()).unwrap_or("Display non valido"))
+                            .show_ui(ui, |ui| {
+                                for display in &available_displays {
+                                    if ui.selectable_value(display_index, display.index, &display.name).clicked() {
+                                        *area = None;
+                                    }
+                                }
+                            });
+                        match area {
+                            Some(rect) => ui.label(format!("Area: ({}, {}) - ({}, {})", rect.min.x as i32, rect.min.y as i32, rect.max.x as i32, rect.max.y as i32)),
+                            None => ui.label("Area: schermo intero"),
+                        };
+                        if ui.button("Seleziona area").clicked() {
+                            select_layer_area = Some((index, *display_index));
+                        }
+                        if ui.add_enabled(area.is_some(), egui::Button::new("Intera")).clicked() {
+                            *area = None;
+                        }
+                    }
+                    LayerSource::Image { path } => {
+                        ui.label("Percorso:");
+                        ui.text_edit_singleline(path);
+                    }
+                    LayerSource::Text { content, size, color } => {
+                        ui.text_edit_singleline(content);
+                        ui.add(egui::DragValue::new(size).range(6.0..=256.0).prefix("dimensione: "));
+                        ui.color_edit_button_srgba(color);
+                    }
+                    LayerSource::Clock { size, color } => {
+                        ui.add(egui::DragValue::new(size).range(6.0..=256.0).prefix("dimensione: "));
+                        ui.color_edit_button_srgba(color);
+                    }
+                }
+            });
+        }
+        if let Some(index) = move_up {
+            scene.layers.swap(index, index + 1);
+        }
+        if let Some(index) = move_down {
+            scene.layers.swap(index, index - 1);
+        }
+        if let Some(index) = remove {
+            scene.layers.remove(index);
+        }
+        drop(settings);
+
+        if let Some(target) = select_layer_area {
+            self.selecting_layer = Some(target);
+            self.capture_screenshot(ctx);
+            self.selecting_area = true;
+            self.start_pos = None;
+            self.status_message = "Clicca e trascina per selezionare l'area del layer".to_string();
+        }
+    }
+
+    fn show_slideshow_controls(&mut self, ui: &mut egui::Ui) {
+        let mut settings = self.capture_settings.write().unwrap();
+        let slideshow = &mut settings.slideshow;
+        ui.horizontal(|ui| {
+            ui.label("Cartella immagini:");
+            ui.text_edit_singleline(&mut slideshow.folder);
+            if ui.button("📂 Carica").clicked() {
+                match slideshow.load_folder() {
+                    Ok(count) => self.status_message = format!("Caricate {} slide", count),
+                    Err(e) => self.status_message = format!("Errore nel caricamento delle slide: {}", e),
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.add_enabled(slideshow.current > 0, egui::Button::new("◀")).clicked() {
+                slideshow.previous();
+            }
+            match slideshow.current_slide() {
+                Some(slide) => ui.label(format!(
+                    "Slide {}/{}: {}",
+                    slideshow.current + 1,
+                    slideshow.slides.len(),
+                    slide.file_name().map(|name| name.to_string_lossy()).unwrap_or_default()
+                )),
+                None => ui.label("Nessuna slide caricata"),
+            };
+            if ui.add_enabled(slideshow.current + 1 < slideshow.slides.len(), egui::Button::new("▶")).clicked() {
+                slideshow.next();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut slideshow.auto_advance, "Avanzamento automatico ogni");
+            ui.add_enabled(slideshow.auto_advance, egui::DragValue::new(&mut slideshow.interval_secs).range(1.0..=600.0).suffix(" s"));
+        });
+    }
+
+    // Salva le annotazioni della pagina corrente, applica il cambio pagina e carica quelle della nuova pagina
+    fn change_whiteboard_page(&mut self, change: impl FnOnce(&mut Whiteboard)) {
+        if let Ok(mut settings) = self.capture_settings.write() {
+            let whiteboard = &mut settings.whiteboard;
+            let current = whiteboard.current;
+            whiteboard.pages[current] = self.whiteboard_annotations.annotations.clone();
+            change(whiteboard);
+            self.whiteboard_annotations.annotations = whiteboard.pages[whiteboard.current].clone();
+            whiteboard.live = self.whiteboard_annotations.annotations.clone();
+            self.whiteboard_annotations.history.clear();
+            self.whiteboard_annotations.reset_interaction();
+        }
+    }
+
+    fn export_whiteboard_pages(&mut self, all_pages: bool) {
+        let settings = self.capture_settings.read().unwrap();
+        let whiteboard = &settings.whiteboard;
+        let timestamp = Local::now().format("%Y%m%d_%H%M%S");
+        let pages: Vec<usize> = if all_pages {
+            (0..whiteboard.pages.len()).collect()
+        } else {
+            vec![whiteboard.current]
+        };
+        for index in &pages {
+            let path = format!("lavagna_{}_pagina_{:02}.png", timestamp, index + 1);
+            if let Err(e) = whiteboard.export_page(*index, Path::new(&path)) {
+                let error = format!("Errore nell'esportazione della lavagna: {}", e);
+                drop(settings);
+                self.set_error(error);
+                return;
+            }
+        }
+        self.status_message = format!("Esportate {} pagine della lavagna", pages.len());
+    }
+
+    fn show_whiteboard_page_controls(&mut self, ui: &mut egui::Ui) {
+        let (current, count) = {
+            let settings = self.capture_settings.read().unwrap();
+            (settings.whiteboard.current, settings.whiteboard.pages.len())
+        };
+        if ui.add_enabled(current > 0, egui::Button::new("◀")).on_hover_text("Pagina precedente").clicked() {
+            self.change_whiteboard_page(Whiteboard::previous);
+        }
+        ui.label(format!("Pagina {}/{}", current + 1, count));
+        if ui.add_enabled(current + 1 < count, egui::Button::new("▶")).on_hover_text("Pagina successiva").clicked() {
+            self.change_whiteboard_page(Whiteboard::next);
+        }
+        if ui.button("➕ Pagina").clicked() {
+            self.change_whiteboard_page(Whiteboard::add_page);
+        }
+    }
+
+    fn show_whiteboard_controls(&mut self, ui: &mut egui::Ui) {
+        if let Ok(mut settings) = self.capture_settings.write() {
+            let whiteboard = &mut settings.whiteboard;
+            ui.horizontal(|ui| {
+                ui.label("Sfondo:");
+                ui.color_edit_button_srgba(&mut whiteboard.background);
+                ui.label("Griglia:");
+                egui::ComboBox::from_id_salt("whiteboard_grid")
+                    .selected_text(match whiteboard.grid {
+                        GridStyle::None => "Nessuna",
+                        GridStyle::Squares => "Quadretti",
+                        GridStyle::Lines => "Righe",
+                        GridStyle::Dots => "Puntini",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut whiteboard.grid, GridStyle::None, "Nessuna");
+                        ui.selectable_value(&mut whiteboard.grid, GridStyle::Squares, "Quadretti");
+                        ui.selectable_value(&mut whiteboard.grid, GridStyle::Lines, "Righe");
+                        ui.selectable_value(&mut whiteboard.grid, GridStyle::Dots, "Puntini");
+                    });
+                ui.add_enabled(
+                    whiteboard.grid != GridStyle::None,
+                    egui::DragValue::new(&mut whiteboard.grid_spacing).range(10.0..=200.0).prefix("passo: "),
+                );
+            });
+        }
+        ui.horizontal(|ui| {
+            self.show_whiteboard_page_controls(ui);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("✏ Apri lavagna").clicked() {
+                self.whiteboard_visible = true;
+            }
+            if ui.button("💾 Esporta pagina").clicked() {
+                self.export_whiteboard_pages(false);
+            }
+            if ui.button("💾 Esporta tutte").clicked() {
+                self.export_whiteboard_pages(true);
+            }
+        });
+    }
+
+    fn show_whiteboard(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("whiteboard_toolbar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let action = ui.vertical(|ui| Self::show_annotation_toolbar(&mut self.whiteboard_annotations, ui, false)).inner;
+                if let Some(action) = action {
+                    self.handle_annotation_file_action(ctx, action, true);
+                }
+                ui.separator();
+                self.show_whiteboard_page_controls(ui);
+                ui.separator();
+                if ui.button("💾 Esporta").clicked() {
+                    self.export_whiteboard_pages(false);
+                }
+                if ui.button("✖ Chiudi lavagna").clicked() {
+                    self.whiteboard_visible = false;
+                }
+            });
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let (width, height, background, grid, spacing, grid_color) = {
+                let settings = self.capture_settings.read().unwrap();
+                let whiteboard = &settings.whiteboard;
+                (whiteboard.width as f32, whiteboard.height as f32, whiteboard.background, whiteboard.grid, whiteboard.grid_spacing.max(4.0), whiteboard.grid_color())
+            };
+            let available = ui.available_rect_before_wrap();
+            let scale = (available.width() / width).min(available.height() / height);
+            let canvas_rect = Rect::from_center_size(available.center(), egui::vec2(width * scale, height * scale));
+            let to_screen = RectTransform::from_to(Rect::from_min_size(Pos2::ZERO, egui::vec2(width, height)), canvas_rect);
+
+            let painter = ui.painter_at(canvas_rect);
+            painter.rect_filled(canvas_rect, 0.0, background);
+            let stroke = egui::Stroke::new(1.0, grid_color);
+            match grid {
+                GridStyle::None => {}
+                GridStyle::Squares | GridStyle::Lines => {
+                    let mut y = spacing;
+                    while y < height {
+                        painter.line_segment([to_screen * Pos2::new(0.0, y), to_screen * Pos2::new(width, y)], stroke);
+                        y += spacing;
+                    }
+                    if grid == GridStyle::Squares {
+                        let mut x = spacing;
+                        while x < width {
+                            painter.line_segment([to_screen * Pos2::new(x, 0.0), to_screen * Pos2::new(x, height)], stroke);
+                            x += spacing;
+                        }
+                    }
+                }
+                GridStyle::Dots => {
+                    let mut y = spacing;
+                    while y < height {
+                        let mut x = spacing;
+                        while x < width {
+                            painter.circle_filled(to_screen * Pos2::new(x, y), 1.5 * scale.max(0.5), grid_color);
+                            x += spacing;
+                        }
+                        y += spacing;
+                    }
+                }
+            }
+
+            Self::handle_annotations(&mut self.whiteboard_annotations, ui, to_screen);
+        });
+
+        // Il caster legge la pagina corrente da capture_settings per rasterizzarla; nella pagina salvata
+        // restano solo le annotazioni permanenti, quelle temporanee vengono solo trasmesse
+        if let Ok(mut settings) = self.capture_settings.write() {
+            let whiteboard = &mut settings.whiteboard;
+            let current = whiteboard.current;
+            if whiteboard.pages[current] != self.whiteboard_annotations.annotations {
+                whiteboard.pages[current] = self.whiteboard_annotations.annotations.clone();
+            }
+            let live = self.whiteboard_annotations.visible_annotations();
+            if whiteboard.live != live {
+                whiteboard.live = live;
+            }
+        }
+    }
+
+    // La lente d'ingrandimento ha senso solo sull'overlay dello schermo catturato, non sulla lavagna
+    fn show_annotation_toolbar(state: &mut AnnotationState, ui: &mut egui::Ui, overlay: bool) -> Option<AnnotationFileAction> {
+        ui.horizontal(|ui| {
+            let mut tool_button = |ui: &mut egui::Ui, tool: AnnotationTool, icon: &str ,label: &str| {
+                let button = egui::Button::new(format!("{icon} {label}"))
+                    .min_size(egui::vec2(40.0, 20.0));
+                let response = ui.add(button);
+
+                if response.clicked(){
+                    state.active_tool = tool;
+                }
+
+                if state.active_tool == tool{
+                    response.clone().highlight();
+                }
+                response.on_hover_text(format!("Usa lo strumento: {label}"));
+            };
+
+            tool_button(ui, AnnotationTool::Select, "🖱", "Seleziona");
+            tool_button(ui, AnnotationTool::Rectangle, "▭", "Rettangolo");
+            tool_button(ui, AnnotationTool::Arrow, "➡", "Freccia");
+            tool_button(ui, AnnotationTool::Text, "📝", "Testo");
+            tool_button(ui, AnnotationTool::Pen, "✏", "Penna");
+            tool_button(ui, AnnotationTool::Highlighter, "🖍", "Evidenziatore");
+            tool_button(ui, AnnotationTool::Line, "╱", "Linea");
+            tool_button(ui, AnnotationTool::Ellipse, "⭕", "Ellisse");
+            tool_button(ui, AnnotationTool::Marker, "①", "Passo numerato");
+            tool_button(ui, AnnotationTool::Eraser, "🧽", "Gomma");
+            tool_button(ui, AnnotationTool::Laser, "🔴", "Laser");
+            tool_button(ui, AnnotationTool::Spotlight, "🔦", "Spotlight");
+            if overlay {
+                tool_button(ui, AnnotationTool::Magnifier, "🔍", "Lente");
+            }
+            // Clear button
+            let clear_button = egui::Button::new("❌ Cancella Tutto")
+                .min_size(egui::vec2(40.0, 20.0));
+
+            if ui.add(clear_button).on_hover_text("Elimina tutte le annotazioni").clicked(){
+                state.clear();
+            }
+
+            ui.separator();
+            if ui.add_enabled(state.history.can_undo(), egui::Button::new("↶ Annulla"))
+                .on_hover_text("Annulla l'ultima modifica (Ctrl+Z)").clicked() {
+                state.undo();
+            }
+            if ui.add_enabled(state.history.can_redo(), egui::Button::new("↷ Ripeti"))
+                .on_hover_text("Ripeti la modifica annullata (Ctrl+Y)").clicked() {
+                state.redo();
+            }
+        });
+        Self::show_style_controls(&mut state.style, ui);
+        Self::show_fade_controls(state, ui);
+        match state.active_tool {
+            AnnotationTool::Spotlight => {
+                ui.horizontal(|ui| {
+                    ui.label("Forma:");
+                    ui.selectable_value(&mut state.spotlight_ellipse, false, "▭ Rettangolo");
+                    ui.selectable_value(&mut state.spotlight_ellipse, true, "⭕ Ellisse");
+                });
+            },
+            AnnotationTool::Magnifier => {
+                ui.horizontal(|ui| {
+                    ui.label("Ingrandimento:");
+                    ui.add(egui::Slider::new(&mut state.magnifier_zoom, 1.5..=6.0).suffix("x"));
+                    ui.label("Raggio:");
+                    ui.add(egui::Slider::new(&mut state.magnifier_radius, 40.0..=300.0).fixed_decimals(0));
+                });
+            },
+            _ => {}
+        }
+        let mut action = None;
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.add(egui::TextEdit::singleline(&mut state.file_path).desired_width(160.0));
+            if ui.button("💾 Salva").on_hover_text("Salva le annotazioni in JSON").clicked() {
+                action = Some(AnnotationFileAction::Save);
+            }
+            if ui.button("📂 Carica").on_hover_text("Carica le annotazioni dal file JSON").clicked() {
+                action = Some(AnnotationFileAction::Load);
+            }
+            if ui.button("⤓ SVG").on_hover_text("Esporta le annotazioni in SVG").clicked() {
+                action = Some(AnnotationFileAction::ExportSvg);
+            }
+            if ui.button("⤓ PNG").on_hover_text("Esporta le annotazioni sopra il frame corrente").clicked() {
+                action = Some(AnnotationFileAction::ExportPng);
+            }
+        });
+        action
+    }
+
+    // Area catturata in punti dello schermo: le annotazioni dell'overlay vengono salvate relative a questa
+    fn overlay_capture_area(&self, ctx: &egui::Context) -> Rect {
+        let pixels_per_point = ctx.pixels_per_point();
+        match self.selected_area {
+            Some(area) => Rect::from_min_max(
+                (area.min.to_vec2() / pixels_per_point).to_pos2(),
+                (area.max.to_vec2() / pixels_per_point).to_pos2(),
+            ),
+            None => ctx.screen_rect(),
+        }
+    }
+
+    // Frame attuale del display catturato, ritagliato sull'area selezionata, in RGBA
+    fn grab_current_frame(&self) -> Result<image::RgbaImage, String> {
+        let mut canvas = DisplayCanvas::open(&self.current_placements())
+            .map_err(|e| format!("Errore nella creazione del capturer: {}", e))?;
+        let (width, height) = (canvas.width, canvas.height);
+        let frame = loop {
+            match canvas.frame() {
+                Ok(frame) => break frame.to_vec(),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+                Err(e) => return Err(format!("Errore nella cattura del frame: {}", e)),
+            }
+        };
+        let (mut cropped, cropped_width, cropped_height) = crop_frame(&frame, width, height, self.selected_area);
+        for pixel in cropped.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            pixel[3] = 255;
+        }
+        image::RgbaImage::from_raw(cropped_width as u32, cropped_height as u32, cropped)
+            .ok_or_else(|| "Frame catturato non valido".to_string())
+    }
+
+    fn handle_annotation_file_action(&mut self, ctx: &egui::Context, action: AnnotationFileAction, whiteboard: bool) {
+        match self.run_annotation_file_action(ctx, action, whiteboard) {
+            Ok(message) => self.status_message = message,
+            Err(error) => self.set_error(error),
+        }
+    }
+
+    fn run_annotation_file_action(&mut self, ctx: &egui::Context, action: AnnotationFileAction, whiteboard: bool) -> Result<String, String> {
+        let (area, pixels_per_point) = if whiteboard {
+            let settings = self.capture_settings.read().unwrap();
+            (Rect::from_min_size(Pos2::ZERO, egui::vec2(settings.whiteboard.width as f32, settings.whiteboard.height as f32)), 1.0)
+        } else {
+            (self.overlay_capture_area(ctx), ctx.pixels_per_point())
+        };
+        let timestamp = Local::now().format("%Y%m%d_%H%M%S");
+        let state = if whiteboard { &mut self.whiteboard_annotations } else { &mut self.annotation_state };
+        let path = Path::new(&state.file_path).to_path_buf();
+        match action {
+            AnnotationFileAction::Save => {
+                AnnotationSet::from_area(&state.annotations, area).save(&path)
+                    .map_err(|e| format!("Errore nel salvataggio delle annotazioni: {}", e))?;
+                Ok(format!("Annotazioni salvate in {}", path.display()))
+            },
+            AnnotationFileAction::Load => {
+                let set = AnnotationSet::load(&path)
+                    .map_err(|e| format!("Errore nel caricamento delle annotazioni: {}", e))?;
+                let count = set.annotations.len();
+                state.set_all(set.to_area(area));
+                Ok(format!("Caricate {} annotazioni da {}", count, path.display()))
+            },
+            AnnotationFileAction::ExportSvg => {
+                let size = area.size() * pixels_per_point;
+                let to_frame = RectTransform::from_to(area, Rect::from_min_size(Pos2::ZERO, size));
+                let annotations: Vec<Annotation> = state.annotations.iter().map(|annotation| annotation.transformed(&to_frame)).collect();
+                let path = format!("annotazioni_{}.svg", timestamp);
+                std::fs::write(&path, svg::render(&annotations, size.x, size.y))
+                    .map_err(|e| format!("Errore nell'esportazione SVG: {}", e))?;
+                Ok(format!("Annotazioni esportate in {}", path))
+            },
+            AnnotationFileAction::ExportPng => {
+                let annotations = state.annotations.clone();
+                let mut image = if whiteboard {
+                    let settings = self.capture_settings.read().unwrap();
+                    let whiteboard = Whiteboard { pages: vec![Vec::new()], current: 0, ..settings.whiteboard.clone() };
+                    whiteboard.render_page(0)
+                } else {
+                    self.grab_current_frame()?
+                };
+                let size = egui::vec2(image.width() as f32, image.height() as f32);
+                let to_frame = RectTransform::from_to(area, Rect::from_min_size(Pos2::ZERO, size));
+                for annotation in &annotations {
+                    raster::draw_annotation(&mut image, &annotation.transformed(&to_frame));
+                }
+                let path = format!("annotazioni_{}.png", timestamp);
+                image.save(&path).map_err(|e| format!("Errore nell'esportazione PNG: {}", e))?;
+                Ok(format!("Annotazioni esportate in {}", path))
+            },
+        }
+    }
+
+    fn show_fade_controls(state: &mut AnnotationState, ui: &mut egui::Ui) {
+        let tool = state.active_tool;
+        if matches!(tool, AnnotationTool::None | AnnotationTool::Select | AnnotationTool::Eraser | AnnotationTool::Laser | AnnotationTool::Magnifier) {
+            return;
+        }
+        ui.horizontal(|ui| {
+            let mut fades = state.fade_after.contains_key(&tool);
+            if ui.checkbox(&mut fades, "Dissolvi dopo").on_hover_text("Le nuove annotazioni di questo strumento scompaiono da sole").changed() {
+                if fades {
+                    state.fade_after.insert(tool, 5.0);
+                } else {
+                    state.fade_after.remove(&tool);
+                }
+            }
+            if let Some(seconds) = state.fade_after.get_mut(&tool) {
+                ui.add(egui::DragValue::new(seconds).range(1.0..=120.0).suffix(" s"));
+            }
+        });
+    }
+
+    // Colore, spessore, riempimento e dimensione del testo applicati alle nuove annotazioni
+    fn show_style_controls(style: &mut AnnotationStyle, ui: &mut egui::Ui) {
+        const PALETTE: [Color32; 8] = [
+            Color32::WHITE,
+            Color32::BLACK,
+            Color32::RED,
+            Color32::from_rgb(255, 140, 0),
+            Color32::YELLOW,
+            Color32::GREEN,
+            Color32::from_rgb(30, 144, 255),
+            Color32::from_rgb(200, 0, 200),
+        ];
+        ui.horizontal(|ui| {
+            let [_, _, _, alpha] = style.color.to_srgba_unmultiplied();
+            for color in PALETTE {
+                let [r, g, b, _] = color.to_srgba_unmultiplied();
+                let swatch = Color32::from_rgba_unmultiplied(r, g, b, alpha);
+                let (rect, response) = ui.allocate_exact_size(egui::vec2(18.0, 18.0), egui::Sense::click());
+                ui.painter().rect_filled(rect, 3.0, color);
+                if style.color == swatch {
+                    ui.painter().rect_stroke(rect.expand(2.0), 3.0, egui::Stroke::new(2.0, ui.visuals().selection.bg_fill));
+                }
+                if response.clicked() {
+                    style.color = swatch;
+                }
+            }
+            ui.color_edit_button_srgba(&mut style.color).on_hover_text("Colore personalizzato");
+
+            ui.separator();
+            ui.label("Opacità:");
+            let mut opacity = alpha as f32 / 255.0;
+            if ui.add(egui::Slider::new(&mut opacity, 0.1..=1.0).fixed_decimals(1)).changed() {
+                let [r, g, b, _] = style.color.to_srgba_unmultiplied();
+                style.color = Color32::from_rgba_unmultiplied(r, g, b, (opacity * 255.0).round() as u8);
+            }
+
+            ui.separator();
+            ui.label("Spessore:");
+            ui.add(egui::Slider::new(&mut style.stroke_width, 1.0..=20.0).fixed_decimals(0));
+
+            ui.separator();
+            let mut filled = style.fill.is_some();
+            if ui.checkbox(&mut filled, "Riempimento").changed() {
+                style.fill = if filled {
+                    let [r, g, b, _] = style.color.to_srgba_unmultiplied();
+                    Some(Color32::from_rgba_unmultiplied(r, g, b, 80))
+                } else {
+                    None
+                };
+            }
+            if let Some(fill) = &mut style.fill {
+                ui.color_edit_button_srgba(fill).on_hover_text("Colore di riempimento");
+            }
+
+            ui.separator();
+            ui.label("Testo:");
+            ui.add(egui::Slider::new(&mut style.font_size, 8.0..=96.0).fixed_decimals(0));
+        });
+    }
+
+
+    fn paint_annotations(painter: &egui::Painter, annotations: &[Annotation], to_screen: RectTransform) {
+        let scale = to_screen.scale().x;
+        for annotation in annotations {
+            match annotation {
+                Annotation::Rectangle{rect, style} => {
+                    painter.rect(
+                        to_screen.transform_rect(*rect),
+                        0.0,
+                        style.fill.unwrap_or(Color32::TRANSPARENT),
+                        egui::Stroke::new(style.stroke_width * scale, style.color),
+                    );
+                },
+                Annotation::Arrow { start, end, style } => {
+                    Self::draw_arrow(painter, to_screen * *start, to_screen * *end, style.color, style.stroke_width * scale);
+                },
+                Annotation::Text { pos, content, style, .. } => {
+                    painter.text(
+                        to_screen * *pos,
+                        egui::Align2::LEFT_TOP,
+                        content,
+                        FontId::proportional(style.font_size * scale),
+                        style.color,
+                    );
+                },
+                Annotation::Line { start, end, style } => {
+                    painter.line_segment([to_screen * *start, to_screen * *end], egui::Stroke::new(style.stroke_width * scale, style.color));
+                },
+                Annotation::Ellipse { rect, style } => {
+                    let rect = to_screen.transform_rect(*rect);
+                    painter.add(egui::Shape::Ellipse(egui::epaint::EllipseShape {
+                        center: rect.center(),
+                        radius: rect.size() / 2.0,
+                        fill: style.fill.unwrap_or(Color32::TRANSPARENT),
+                        stroke: egui::Stroke::new(style.stroke_width * scale, style.color),
+                    }));
+                },
+                Annotation::Freehand { points, style } => {
+                    Self::draw_freehand(painter, points, style, to_screen);
+                },
+                Annotation::Marker { pos, number, style } => {
+                    let center = to_screen * *pos;
+                    painter.circle_filled(center, marker_radius(style.font_size) * scale, style.color);
+                    painter.text(
+                        center,
+                        egui::Align2::CENTER_CENTER,
+                        number.to_string(),
+                        FontId::proportional(style.font_size * scale),
+                        marker_text_color(style.color),
+                    );
+                },
+                Annotation::Spotlight { rect, ellipse, style } => {
+                    Self::paint_spotlight(painter, to_screen.transform_rect(*rect), *ellipse, *to_screen.to(), style.fill.unwrap_or(SPOTLIGHT_DIM));
+                },
+            }
+        }
+    }
+
+    // Oscura area tranne hole: quattro rettangoli attorno al buco, oppure un anello tra il bordo e l'ellisse
+    fn paint_spotlight(painter: &egui::Painter, hole: Rect, ellipse: bool, area: Rect, dim: Color32) {
+        let painter = painter.with_clip_rect(painter.clip_rect().intersect(area));
+        let radius = hole.size() / 2.0;
+        if !hole.intersects(area) || radius.x <= 0.0 || radius.y <= 0.0 {
+            painter.rect_filled(area, 0.0, dim);
+            return;
+        }
+        if !ellipse {
+            let hole = hole.intersect(area);
+            let sides = [
+                Rect::from_min_max(area.min, Pos2::new(area.max.x, hole.min.y)),
+                Rect::from_min_max(Pos2::new(area.min.x, hole.max.y), area.max),
+                Rect::from_min_max(Pos2::new(area.min.x, hole.min.y), Pos2::new(hole.min.x, hole.max.y)),
+                Rect::from_min_max(Pos2::new(hole.max.x, hole.min.y), Pos2::new(area.max.x, hole.max.y)),
+            ];
+            for side in sides.into_iter().filter(|side| side.is_positive()) {
+                painter.rect_filled(side, 0.0, dim);
+            }
+            return;
+        }
+        let outer = area.union(hole).expand(1.0);
+        let center = hole.center();
+        // Raggi dal centro: campionamento regolare più gli angoli del bordo, così l'anello lo copre tutto
+        let mut angles: Vec<f32> = (0..96).map(|i| i as f32 / 96.0 * std::f32::consts::TAU).collect();
+        for corner in [outer.left_top(), outer.right_top(), outer.right_bottom(), outer.left_bottom()] {
+            angles.push((corner - center).angle().rem_euclid(std::f32::consts::TAU));
+        }
+        angles.sort_by(f32::total_cmp);
+        let mut mesh = egui::Mesh::default();
+        for angle in &angles {
+            let dir = egui::Vec2::angled(*angle);
+            let inner = 1.0 / ((dir.x / radius.x).powi(2) + (dir.y / radius.y).powi(2)).sqrt();
+            let to_x = if dir.x > 0.0 { (outer.max.x - center.x) / dir.x } else if dir.x < 0.0 { (outer.min.x - center.x) / dir.x } else { f32::INFINITY };
+            let to_y = if dir.y > 0.0 { (outer.max.y - center.y) / dir.y } else if dir.y < 0.0 { (outer.min.y - center.y) / dir.y } else { f32::INFINITY };
+            mesh.colored_vertex(center + dir * inner, dim);
+            mesh.colored_vertex(center + dir * to_x.min(to_y).max(inner), dim);
+        }
+        let count = angles.len() as u32;
+        for i in 0..count {
+            let next = (i + 1) % count;
+            mesh.add_triangle(2 * i, 2 * i + 1, 2 * next + 1);
+            mesh.add_triangle(2 * i, 2 * next + 1, 2 * next);
+        }
+        painter.add(egui::Shape::mesh(mesh));
+    }
+
+    // Vista ingrandita prodotta dal caster, ritagliata a cerchio; view_rect è la zona dello schermo che copre
+    // Nell'overlay la lente è solo il contorno: il contenuto ingrandito verrebbe catturato e ingrandito di nuovo.
+    // Il contorno cade fuori dalla zona letta dalla lente (raggio / zoom), quindi non finisce nell'ingrandimento
+    fn paint_magnifier(painter: &egui::Painter, center: Pos2, radius: f32) {
+        painter.circle_stroke(center, radius, egui::Stroke::new(3.0, Color32::from_gray(200)));
+    }
+
+    fn draw_freehand(painter: &egui::Painter, points: &[Pos2], style: &AnnotationStyle, to_screen: RectTransform) {
+        let width = style.stroke_width * to_screen.scale().x;
+        match points {
+            [] => {},
+            [point] => {
+                painter.circle_filled(to_screen * *point, width / 2.0, style.color);
+            },
+            _ => {
+                let points = points.iter().map(|point| to_screen * *point).collect();
+                painter.add(egui::Shape::line(points, egui::Stroke::new(width, style.color)));
+            },
+        }
+    }
+
+    // L'evidenziatore è un tratto a mano libera largo e semitrasparente
+    fn highlighter_style(style: &AnnotationStyle) -> AnnotationStyle {
+        let [r, g, b, _] = style.color.to_srgba_unmultiplied();
+        AnnotationStyle {
+            color: Color32::from_rgba_unmultiplied(r, g, b, 90),
+            stroke_width: (style.stroke_width * 4.0).max(12.0),
+            fill: None,
+            ..*style
+        }
+    }
+
+    // Elenco delle registrazioni interrotte, da completare con ffmpeg o eliminare
+    fn show_unfinished_recordings(&mut self, ui: &mut egui::Ui) {
+        if self.unfinished_recordings.is_empty() {
+            return;
+        }
+        ui.separator();
+        ui.label(egui::RichText::new("Registrazioni non completate").strong());
+        let mut removed = None;
+        for (index, recording) in self.unfinished_recordings.iter().enumerate() {
+            let status = recording.status.read().unwrap().clone();
+            ui.horizontal(|ui| {
+                ui.label(format!("{} ({} frame)", recording.dir.display(), recording.frames));
+                match status {
+                    RecoveryStatus::Pending | RecoveryStatus::Failed(_) => {
+                        if ui.button("Completa").clicked() {
+                            let receiver_state = self.receiver_state.read().unwrap();
+                            recording.finalize(&receiver_state.jobs, &receiver_state.settings);
+                        }
+                        if ui.button("🗑 Elimina").on_hover_text("Elimina i frame non ancora convertiti; i video già pronti restano").clicked() {
+                            match recording.delete() {
+                                Ok(_) => removed = Some(index),
+                                Err(e) => self.set_error(format!("Impossibile eliminare la registrazione: {}", e)),
+                            }
+                        }
+                        if let RecoveryStatus::Failed(error) = &status {
+                            ui.colored_label(Color32::from_rgb(255, 80, 80), format!("Conversione fallita: {}", error));
+                        }
+                    }
+                    RecoveryStatus::Converting => {
+                        ui.spinner();
+                        ui.label("Conversione in corso...");
+                        ui.ctx().request_repaint_after(Duration::from_millis(500));
+                    }
+                    RecoveryStatus::Done => {
+                        ui.label("✔ Video creato");
+                        if ui.button("OK").clicked() {
+                            removed = Some(index);
+                        }
+                    }
+                }
+            });
+        }
+        if let Some(index) = removed {
+            self.unfinished_recordings.remove(index);
+        }
+    }
+
+    fn save_recording_settings(&self, settings: &RecordingSettings) {
+        if let Err(e) = settings.save() {
+            self.set_error(format!("Impossibile salvare le impostazioni di registrazione: {}", e));
+        }
+    }
+
+    // Le modifiche dalla barra di registrazione vengono salvate solo a trascinamento o digitazione conclusi
+    fn follow_recording_settings(&mut self, ctx: &egui::Context) {
+        if !self.recording_settings_unsaved || ctx.dragged_id().is_some() || ctx.memory(|memory| memory.focused().is_some()) {
+            return;
+        }
+        self.recording_settings_unsaved = false;
+        let settings = self.receiver_state.read().unwrap().settings.clone();
+        self.save_recording_settings(&settings);
+    }
+
+    // La verifica avvia ffmpeg e scrive nella cartella di destinazione: viene eseguita in background
+    // e l'esito arriva a follow_settings_check
+    fn check_recording_settings(&mut self, ctx: &egui::Context, settings: RecordingSettings, purpose: SettingsCheck) {
+        let (sender, receiver) = mpsc::channel();
+        let ctx = ctx.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = settings.validate();
+            let _ = sender.send((settings, result));
+            ctx.request_repaint();
+        });
+        self.settings_check = Some((purpose, receiver));
+    }
+
+    fn follow_settings_check(&mut self, ctx: &egui::Context) {
+        let Some((purpose, receiver)) = &self.settings_check else {
+            return;
+        };
+        let purpose = *purpose;
+        let (settings, result) = match receiver.try_recv() {
+            Ok(outcome) => outcome,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.settings_check = None;
+                self.set_error("Verifica delle impostazioni di registrazione interrotta".to_string());
+                return;
+            }
+        };
+        self.settings_check = None;
+        match (purpose, result) {
+            (SettingsCheck::Verify, Ok(_)) => self.status_message = "Impostazioni di registrazione valide.".to_string(),
+            (SettingsCheck::Verify, Err(e)) => self.set_error(format!("Impostazioni di registrazione non valide: {}", e)),
+            // Il receiver può essere stato fermato durante la verifica
+            (SettingsCheck::Start, Ok(_)) if !self.receiver_running.load(Ordering::SeqCst) => {},
+            (SettingsCheck::Start, Ok(streaming)) => {
+                let receiver_state = Arc::clone(&self.receiver_state);
+                let mut receiver_state = receiver_state.write().unwrap();
+                // Le impostazioni sono cambiate durante la verifica: si ricontrollano quelle nuove
+                if receiver_state.settings != settings {
+                    let settings = receiver_state.settings.clone();
+                    drop(receiver_state);
+                    self.check_recording_settings(ctx, settings, SettingsCheck::Start);
+                    return;
+                }
+                match receiver_state.start_recording(streaming) {
+                    Ok(_) if !receiver_state.saving_png() => {
+                        self.status_message = "Registrazione avviata.".to_string();
+                        self.clear_error();
+                    },
+                    Ok(_) => {
+                        self.status_message = "FFmpeg non trovato: i frame vengono salvati come PNG, installare FFmpeg per convertirli in video.".to_string();
+                        self.clear_error();
+                    },
+                    Err(e) => {
+                        self.handle_recording_error(e.to_string());
+                    }
+                }
+            },
+            (SettingsCheck::Start, Err(e)) => self.handle_recording_error(e.to_string()),
+        }
+    }
+
+    // Barra della cronologia sotto lo stream: pausa, salti indietro e avanti e ritorno alla diretta.
+    // In visione differita aggiorna la texture con il frame della cronologia
+    fn show_timeline(&mut self, ui: &mut egui::Ui) {
+        let receiver_state = Arc::clone(&self.receiver_state);
+        let receiver_state = receiver_state.read().unwrap();
+        let replay = &receiver_state.replay;
+        let (Some(first), Some(last)) = (replay.first_received(), replay.last_received()) else {
+            return;
+        };
+        if receiver_state.settings.history_minutes == 0 {
+            self.time_shift = None;
+            return;
+        }
+        // La cronologia scarta i frame più vecchi: la posizione resta dentro quelli disponibili
+        if let Some(shift) = &mut self.time_shift {
+            if shift.current() < first {
+                shift.seek(first);
+            }
+            if shift.playing_since.is_some() && shift.current() >= last {
+                self.time_shift = None;
+            }
+        }
+        let position = self.time_shift.as_ref().map(|shift| shift.current().min(last)).unwrap_or(last);
+        let history = last.duration_since(first).as_secs_f32();
+        let step = Duration::from_secs(10);
+
+        ui.horizontal(|ui| {
+            let playing = self.time_shift.as_ref().is_none_or(|shift| shift.playing_since.is_some());
+            if playing {
+                if ui.add_enabled(history > 0.0, egui::Button::new("⏸ Pausa")).clicked() {
+                    match &mut self.time_shift {
+                        Some(shift) => shift.set_playing(false),
+                        None => self.time_shift = Some(TimeShift::new(last, false)),
+                    }
+                }
+            } else if ui.button("▶ Riprendi").clicked() {
+                if let Some(shift) = &mut self.time_shift {
+                    shift.set_playing(true);
+                }
+            }
+            if ui.add_enabled(position > first, egui::Button::new("⏪ 10 s")).clicked() {
+                let target = position.checked_sub(step).unwrap_or(first).max(first);
+                match &mut self.time_shift {
+                    Some(shift) => shift.seek(target),
+                    None => self.time_shift = Some(TimeShift::new(target, true)),
+                }
+            }
+            if ui.add_enabled(self.time_shift.is_some(), egui::Button::new("⏩ 10 s")).clicked() {
+                let target = position + step;
+                if target >= last {
+                    self.time_shift = None;
+                } else if let Some(shift) = &mut self.time_shift {
+                    shift.seek(target);
+                }
+            }
+
+            let mut offset = -last.duration_since(position).as_secs_f32();
+            ui.spacing_mut().slider_width = (ui.available_width() - 220.0).max(100.0);
+            let slider = ui.add(egui::Slider::new(&mut offset, -history..=0.0).show_value(false))
+                .on_hover_text("Trascina per rivedere la diretta");
+            if slider.changed() {
+                let target = last.checked_sub(Duration::from_secs_f32(-offset)).unwrap_or(first).max(first);
+                if offset >= 0.0 {
+                    self.time_shift = None;
+                } else {
+                    match &mut self.time_shift {
+                        Some(shift) => shift.seek(target),
+                        None => self.time_shift = Some(TimeShift::new(target, true)),
+                    }
+                }
+            }
+
+            if self.time_shift.is_some() {
+                let behind = last.duration_since(position).as_secs();
+                ui.label(format!("-{:02}:{:02}", behind / 60, behind % 60));
+                if ui.button("⏭ Torna alla diretta").clicked() {
+                    self.time_shift = None;
+                }
+            } else {
+                ui.label(egui::RichText::new("● IN DIRETTA").color(Color32::from_rgb(255, 0, 0)).strong());
+            }
+        });
+
+        let Some(shift) = &mut self.time_shift else {
+            return;
+        };
+        // Un frame alla volta viene decodificato fuori dal thread della UI; trascinando la barra si mostrano
+        // i frame man mano che sono pronti e si riparte dalla posizione più recente
+        if let Some((received, receiver)) = &shift.decoding {
+            match receiver.try_recv() {
+                Ok(image) => {
+                    shift.shown = Some(*received);
+                    shift.decoding = None;
+                    if let Some(image) = image {
+                        self.stream_texture = Some(ui.ctx().load_texture("stream", image, egui::TextureOptions::LINEAR));
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => shift.decoding = None,
+            }
+        }
+        let Some((received, jpeg)) = replay.frame_at(shift.current()) else {
+            return;
+        };
+        if shift.shown == Some(received) {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let ctx = ui.ctx().clone();
+        tokio::task::spawn_blocking(move || {
+            let image = match image::load_from_memory(&jpeg) {
+                Ok(img) => {
+                    let img = img.to_rgba8();
+                    Some(egui::ColorImage::from_rgba_unmultiplied([img.width() as usize, img.height() as usize], &img))
+                }
+                Err(e) => {
+                    eprintln!("Errore nella decodifica del frame della cronologia: {}", e);
+                    None
+                }
+            };
+            let _ = sender.send(image);
+            ctx.request_repaint();
+        });
+        shift.decoding = Some((received, receiver));
+    }
+
+    // Salva in background il contenuto del buffer del replay; gli errori arrivano come errori di registrazione
+    fn save_replay(&mut self, receiver_state: &ReceiverState) {
+        let length = Duration::from_secs(receiver_state.settings.replay_seconds as u64);
+        let frames = receiver_state.replay.snapshot(length);
+        let settings = receiver_state.settings.clone();
+        let jobs = receiver_state.jobs.clone();
+        let shared_state = Arc::clone(&self.receiver_state);
+        self.status_message = format!("Salvataggio degli ultimi {} secondi...", receiver_state.replay.duration().min(length).as_secs());
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = replay::write_replay(&frames, &settings, &jobs) {
+                eprintln!("Salvataggio del replay fallito: {}", e);
+                if let Ok(mut state) = shared_state.write() {
+                    state.recording_error = Some(format!("salvataggio del replay fallito: {}", e));
+                }
+            }
+        });
+    }
+
+    // Destinazione e codifica delle registrazioni; non modificabili mentre si registra.
+    // Si modifica una copia: le impostazioni vengono applicate e salvate con Applica o alla chiusura della finestra
+    fn show_recording_settings(&mut self, ctx: &egui::Context) {
+        let Some(mut settings) = self.recording_settings_draft.take() else {
+            return;
+        };
+        let mut open = true;
+        let (recording, current) = {
+            let receiver_state = self.receiver_state.read().unwrap();
+            (receiver_state.recording, receiver_state.settings.clone())
+        };
+        let checking = self.settings_check.is_some();
+        let mut apply = false;
+        let mut verify = false;
+        egui::Window::new("Impostazioni di registrazione")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(!recording && !checking, |ui| {
+                    egui::Grid::new("recording_settings").num_columns(2).spacing([12.0, 6.0]).show(ui, |ui| {
+                        ui.label("Cartella di destinazione");
+                        ui.text_edit_singleline(&mut settings.output_dir);
+                        ui.end_row();
+
+                        ui.label("Nome della registrazione");
+                        ui.text_edit_singleline(&mut settings.filename_template)
+                            .on_hover_text("{date} e {time} vengono sostituiti con data e ora di avvio");
+                        ui.end_row();
+
+                        ui.label("Formato");
+                        egui::ComboBox::from_id_salt("settings_format")
+                            .selected_text(settings.format.label())
+                            .show_ui(ui, |ui| {
+                                for format in RecordingFormat::ALL {
+                                    ui.selectable_value(&mut settings.format, format, format.label());
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Codec");
+                        egui::ComboBox::from_id_salt("settings_codec")
+                            .selected_text(settings.codec.label())
+                            .show_ui(ui, |ui| {
+                                for codec in VideoCodec::ALL {
+                                    ui.selectable_value(&mut settings.codec, codec, codec.label());
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Qualità");
+                        egui::ComboBox::from_id_salt("settings_quality")
+                            .selected_text(settings.quality.label())
+                            .show_ui(ui, |ui| {
+                                for quality in Quality::ALL {
+                                    ui.selectable_value(&mut settings.quality, quality, quality.label());
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Replay istantaneo");
+                        ui.add(egui::DragValue::new(&mut settings.replay_seconds).range(0..=600).suffix(" s"))
+                            .on_hover_text("Secondi di stream tenuti in memoria per \"Salva ultimi\"; 0 lo disattiva");
+                        ui.end_row();
+
+                        ui.label("Cronologia della diretta");
+                        ui.add(egui::DragValue::new(&mut settings.history_minutes).range(0..=120).suffix(" min"))
+                            .on_hover_text("Minuti di stream che si possono rivedere con la barra sotto il video; 0 la disattiva");
+                        ui.end_row();
+
+                        ui.label("Eseguibile FFmpeg");
+                        ui.text_edit_singleline(&mut settings.ffmpeg_path)
+                            .on_hover_text("Percorso completo di ffmpeg, oppure \"ffmpeg\" per usare quello nel PATH");
+                        ui.end_row();
+                    });
+                    ui.label(format!("Esempio: {}", Path::new(settings.output_dir.trim())
+                        .join(settings.recording_name())
+                        .join(format!("{}.{}", settings.recording_name(), settings.format.extension()))
+                        .display()));
+                    ui.horizontal(|ui| {
+                        apply = ui.add_enabled(settings != current, egui::Button::new("Applica")).clicked();
+                        verify = ui.button("Verifica").clicked();
+                        if checking {
+                            ui.spinner();
+                        }
+                    });
+                });
+            });
+        if verify {
+            self.check_recording_settings(ctx, settings.clone(), SettingsCheck::Verify);
+        }
+        if (apply || !open) && !recording && settings != current {
+            self.receiver_state.write().unwrap().settings = settings.clone();
+            self.save_recording_settings(&settings);
+        }
+        if open {
+            self.recording_settings_draft = Some(settings);
+        }
+    }
+
+    // Avanzamento delle conversioni in background, con il file pronto o l'errore di ffmpeg al termine
+    fn show_conversion_jobs(&mut self, ui: &mut egui::Ui) {
+        let jobs = self.receiver_state.read().unwrap().jobs.clone();
+        let list = jobs.list();
+        if list.is_empty() {
+            return;
+        }
+        ui.separator();
+        ui.label(egui::RichText::new("Conversioni delle registrazioni").strong());
+        for job in list {
+            ui.horizontal(|ui| {
+                ui.label(job.output.display().to_string());
+                match &job.status {
+                    JobStatus::Running(Some(progress)) => {
+                        ui.add(egui::ProgressBar::new(*progress).show_percentage().desired_width(200.0));
+                    }
+                    JobStatus::Running(None) => {
+                        ui.spinner();
+                        ui.label("In attesa di ffmpeg...");
+                    }
+                    JobStatus::Done => {
+                        ui.label("✔ Pronto");
+                        if ui.button("📂 Mostra file").clicked() {
+                            if let Err(e) = recorder::reveal(&job.output) {
+                                self.set_error(format!("Impossibile aprire la cartella: {}", e));
+                            }
+                        }
+                        if ui.button("OK").clicked() {
+                            jobs.dismiss(job.id);
+                        }
+                    }
+                    JobStatus::Failed(error) => {
+                        ui.colored_label(Color32::from_rgb(255, 80, 80), format!("Conversione fallita: {}", error));
+                        if ui.button("OK").clicked() {
+                            jobs.dismiss(job.id);
+                        }
+                    }
+                }
+            });
+        }
+        if jobs.running() {
+            ui.ctx().request_repaint_after(Duration::from_millis(250));
         }
     }
 
-    fn draw_arrow(painter: &egui::Painter, start: egui::Pos2, end: egui::Pos2, color: egui::Color32) {
-        let stroke = egui::Stroke::new(2.0, color);
-
-        let dir = end - start;
-        let length = dir.length();
-        if length < 5.0 {
+    // Chiudere durante una conversione lascerebbe il video incompleto: la chiusura attende la fine delle conversioni
+    fn guard_close(&mut self, ctx: &egui::Context) {
+        let jobs = self.receiver_state.read().unwrap().jobs.clone();
+        if ctx.input(|i| i.viewport().close_requested()) && jobs.running() && !self.force_close {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.close_pending = true;
+        }
+        if !self.close_pending {
             return;
         }
+        if !jobs.running() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+        egui::Window::new("Conversioni in corso")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("Alcune registrazioni sono ancora in conversione: l'applicazione si chiuderà appena terminano.");
+                ui.horizontal(|ui| {
+                    if ui.button("Esci comunque").clicked() {
+                        self.force_close = true;
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    }
+                    if ui.button("Annulla").clicked() {
+                        self.close_pending = false;
+                    }
+                });
+            });
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
 
-        let dir_normalized = dir / length;
-
-        let arrowhead_length = 16.0;
-        let arrowhead_width = 10.0;
-        let tip = end;
-        let base = end - dir_normalized * arrowhead_length;
-
-        let perp = egui::Vec2::new(-dir_normalized.y, dir_normalized.x) * (arrowhead_width / 2.0);
-        let left = base + perp;
-        let right = base - perp;
-
-        let line_end = base;
-        painter.line_segment([start, line_end], stroke);
-
-        let points = vec![tip, left, right];
-        painter.add(egui::Shape::convex_polygon(points, color, stroke));
+    // Segue la pagina di contenuto pubblicata dal caster, che cambia anche quando l'interfaccia è ferma
+    fn follow_content_page(&mut self, ctx: &egui::Context) {
+        let caster_running = self.caster_running.load(Ordering::SeqCst);
+        let (enabled, page) = {
+            let settings = self.capture_settings.read().unwrap();
+            (settings.content_pages, settings.content_page)
+        };
+        self.annotation_state.show_content_page(page.filter(|_| enabled && caster_running));
+        if enabled && caster_running {
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
     }
 
-    fn capture_screenshot(&mut self, ctx: &egui::Context) {
-        let display_index = match self.selected_display_index {
-            Some(index) => index,
-            None => {
-                self.set_error("Nessun display selezionato".to_string());
-                return;
+    // Converte le annotazioni dell'overlay (punti della finestra, massimizzata sul display catturato)
+    // in pixel del frame trasmesso, relativi all'area selezionata
+    fn sync_overlay_annotations(&mut self, ctx: &egui::Context) {
+        let pixels_per_point = ctx.pixels_per_point();
+        let offset = self.selected_area.map(|area| area.min.to_vec2()).unwrap_or_default();
+        let to_frame = RectTransform::from_to(
+            Rect::from_min_size(Pos2::ZERO, egui::vec2(1.0, 1.0)),
+            Rect::from_min_size(Pos2::ZERO - offset, egui::vec2(pixels_per_point, pixels_per_point)),
+        );
+        let overlay_visible = self.toolbar_visible && self.caster_running.load(Ordering::SeqCst);
+        if let Ok(mut settings) = self.capture_settings.write() {
+            let annotations = if overlay_visible && settings.source == CaptureSource::Screen && !self.combine_displays {
+                self.annotation_state.visible_annotations().iter().map(|annotation| annotation.transformed(&to_frame)).collect()
+            } else {
+                Vec::new()
+            };
+            let uses_escape = self.annotation_state.selected.is_some() || self.annotation_state.editing_text.is_some();
+            if overlay_visible && uses_escape {
+                settings.escape_reserved_until = Some(Instant::now() + ESCAPE_GRACE);
             }
-        };
-        let displays = match Display::all() {
-            Ok(displays) => {
-                displays },
-            Err(e) => {
-                self.set_error(format!("Errore nell'accesso ai display: {}", e));
-                return;
+            let throttled = !self.annotation_state.laser_trail.is_empty() && self.laser_synced.elapsed() < LASER_SYNC_INTERVAL;
+            if settings.annotations != annotations && !throttled {
+                settings.annotations = annotations;
+                self.laser_synced = Instant::now();
             }
-        };
-
-        if display_index >= displays.len() {
-            self.set_error("Indice del display non valido".to_string());
-            return;
+            let pointer = ctx.input(|i| i.pointer.hover_pos());
+            settings.magnifier = match pointer {
+                Some(pos) if overlay_visible && self.annotation_state.active_tool == AnnotationTool::Magnifier => Some(Magnifier {
+                    center: to_frame * pos,
+                    radius: self.annotation_state.magnifier_radius * pixels_per_point,
+                    zoom: self.annotation_state.magnifier_zoom,
+                }),
+                _ => None,
+            };
         }
+    }
 
-        let target_display = displays.into_iter().nth(display_index).unwrap();
-        let width= target_display.width();
-        let height =target_display.height();
-        let mut capturer = match Capturer::new(target_display) {
-            Ok(capturer) => {
-                capturer },
-            Err(e) => {
-                self.set_error(format!("Errore nella creazione del capturer: {}", e));
-                return;
+    // Opzione per imprimere le annotazioni nei frame trasmessi, con un'anteprima generata dallo stesso rasterizzatore del caster
+    fn show_burn_controls(&mut self, ui: &mut egui::Ui) {
+        let (annotations, area_size) = {
+            let mut settings = self.capture_settings.write().unwrap();
+            ui.checkbox(&mut settings.content_pages, "Pagine di annotazioni legate al contenuto")
+                .on_hover_text("Al cambio di slide le annotazioni vengono messe da parte e ricompaiono se la stessa slide torna sullo schermo");
+            if settings.content_pages && !self.annotation_state.content_pages.is_empty() {
+                ui.label(format!("Pagine con annotazioni salvate: {}", self.annotation_state.content_pages.len()));
             }
-        };
-        let frame = loop {
-            match capturer.frame() {
-                Ok(frame) => {
-                    break frame; },
-                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(100));
-                    continue;
-                }
-                Err(e) => {
-                    eprintln!("Errore nella cattura del frame: {}", e);
-                    return;
-                }
+            ui.checkbox(&mut settings.burn_annotations, "Imprimi le annotazioni nei frame trasmessi")
+                .on_hover_text("Le annotazioni saranno visibili anche nelle registrazioni dei receiver");
+            if !settings.burn_annotations {
+                self.burn_preview = None;
+                return;
             }
+            let area_size = self.selected_area
+                .map(|area| area.size())
+                .unwrap_or_else(|| ui.ctx().screen_rect().size() * ui.ctx().pixels_per_point());
+            (settings.annotations.clone(), area_size)
         };
-        let stride = frame.len() / height;
-
-        let mut img_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(
-           width as u32,
-           height as u32
-        );
-
-        for y in 0..height {
-            for x in 0..width {
-                let idx = y * stride + x * 4;
-                if idx + 3 < frame.len() {
-                    let b = frame[idx];
-                    let g = frame[idx + 1];
-                    let r = frame[idx + 2];
-                    img_buffer.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
-                }
-            }
+        if annotations.is_empty() {
+            self.burn_preview = None;
+            return;
         }
+        let preview_width = 320.0;
+        let scale = preview_width / area_size.x.max(1.0);
+        let preview_size = egui::vec2(preview_width, (area_size.y * scale).max(1.0));
+        if self.burn_preview.as_ref().is_none_or(|(shown, _)| *shown != annotations) {
+            let to_preview = RectTransform::from_to(
+                Rect::from_min_size(Pos2::ZERO, area_size),
+                Rect::from_min_size(Pos2::ZERO, preview_size),
+            );
+            let scaled: Vec<Annotation> = annotations.iter().map(|annotation| annotation.transformed(&to_preview)).collect();
+            let image = raster::render_annotations(&scaled, preview_size.x as u32, preview_size.y as u32);
+            let color_image = egui::ColorImage::from_rgba_unmultiplied([image.width() as usize, image.height() as usize], image.as_raw());
+            let texture = ui.ctx().load_texture("burn_preview", color_image, egui::TextureOptions::LINEAR);
+            self.burn_preview = Some((annotations, texture));
+        }
+        if let Some((_, texture)) = &self.burn_preview {
+            ui.label("Anteprima delle annotazioni impresse:");
+            let (rect, _) = ui.allocate_exact_size(preview_size, egui::Sense::hover());
+            ui.painter().rect_filled(rect, 0.0, Color32::from_gray(30));
+            ui.painter().image(texture.id(), rect, Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)), Color32::WHITE);
+        }
+    }
 
-        let color_image = egui::ColorImage::from_rgba_unmultiplied(
-            [width, height],
-            &img_buffer.into_raw(),
-        );
-
-        self.screenshot = Some(ctx.load_texture(
-            "screenshot",
-            color_image,
-            egui::TextureOptions::LINEAR,
-        ));
-        ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(true));
-        ctx.send_viewport_cmd(egui::ViewportCommand::Transparent(false));
+    fn show_magnifier(&mut self, ui: &mut egui::Ui) {
+        if let Some(center) = ui.input(|i| i.pointer.hover_pos()) {
+            Self::paint_magnifier(ui.painter(), center, self.annotation_state.magnifier_radius);
+        }
     }
-    fn show_annotation_toolbar(&mut self, ui: &mut egui::Ui) {
-        ui.horizontal(|ui| {
-            let mut tool_button = |ui: &mut egui::Ui, tool: AnnotationTool, icon: &str ,label: &str| {
-                let button = egui::Button::new(format!("{icon} {label}"))
-                    .min_size(egui::vec2(40.0, 20.0));
-                let response = ui.add(button);
 
-                if response.clicked(){
-                    self.annotation_state.active_tool = tool.clone();
+    // to_screen converte le coordinate delle annotazioni in coordinate dello schermo:
+    // identità per l'overlay, scala del canvas per la lavagna
+    fn handle_annotations(state: &mut AnnotationState, ui: &mut egui::Ui, to_screen: RectTransform) {
+        let from_screen = to_screen.inverse();
+        let scale = to_screen.scale().x;
+
+        // Le scorciatoie non devono interferire con la modifica di un testo
+        if !ui.ctx().wants_keyboard_input() {
+            let (undo, redo) = ui.input_mut(|i| {
+                let redo = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)
+                    || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+                (i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z), redo)
+            });
+            if undo {
+                state.undo();
+            }
+            if redo {
+                state.redo();
+            }
+            if state.active_tool == AnnotationTool::Select {
+                let (delete, escape) = ui.input(|i| {
+                    (i.key_pressed(egui::Key::Delete) || i.key_pressed(egui::Key::Backspace), i.key_pressed(egui::Key::Escape))
+                });
+                if delete {
+                    if let Some(index) = state.selected.take().filter(|index| *index < state.annotations.len()) {
+                        state.remove(index);
+                    }
                 }
-
-                if self.annotation_state.active_tool == tool{
-                    response.clone().highlight();
+                if escape {
+                    state.selected = None;
                 }
-                response.on_hover_text(format!("Usa lo strumento: {label}"));
-            };
-
-            tool_button(ui, AnnotationTool::Rectangle, "▭", "Rettangolo");
-            tool_button(ui, AnnotationTool::Arrow, "➡", "Freccia");
-            tool_button(ui, AnnotationTool::Text, "📝", "Testo");
-            // Clear button
-            let clear_button = egui::Button::new("❌ Cancella Tutto")
-                .min_size(egui::vec2(40.0, 20.0));
-
-            if ui.add(clear_button).on_hover_text("Elimina tutte le annotazioni").clicked(){
-                self.annotation_state.annotations.clear();
             }
-        });
-    }
-
-
-    fn handle_annotations(&mut self, ui: &mut egui::Ui) {
-        let pointer_pos = ui.input(|i| i.pointer.hover_pos());
+        }
+        let pointer_pos = ui.input(|i| i.pointer.hover_pos()).map(|pos| from_screen * pos);
         let mouse_pressed = ui.input(|i| i.pointer.primary_pressed());
+        let mouse_down = ui.input(|i| i.pointer.primary_down());
         let mouse_released = ui.input(|i| i.pointer.primary_released());
+        let double_clicked = ui.input(|i| i.pointer.button_double_clicked(egui::PointerButton::Primary));
+        let tolerance = 6.0 / scale;
 
         if let Some(pos) = pointer_pos {
-            if mouse_pressed {
-                self.annotation_state.start_pos = Some(pos);
-                if self.annotation_state.active_tool == AnnotationTool::Text
-                    && self.annotation_state.editing_text.is_none() {
-                    self.annotation_state.editing_text = Some(String::new());
-                    self.annotation_state.text_edit_id = Some(egui::Id::new("text_edit"));
+            if state.editing_text.is_some() {
+                // Mentre si scrive un testo i clic servono solo a chiuderlo, tramite la perdita del focus
+            } else if double_clicked && state.active_tool == AnnotationTool::Select {
+                if let Some(index) = Self::text_at(state, pos, tolerance) {
+                    Self::start_text_edit(state, ui, index);
+                }
+            } else if mouse_pressed && to_screen.from().contains(pos) {
+                match state.active_tool {
+                    AnnotationTool::Select => {
+                        Self::begin_drag(state, pos, tolerance);
+                    },
+                    AnnotationTool::Text => {
+                        if let Some(index) = Self::text_at(state, pos, tolerance) {
+                            Self::start_text_edit(state, ui, index);
+                        } else {
+                            let id = egui::Id::new("text_edit");
+                            state.start_pos = Some(pos);
+                            state.editing_text = Some(String::new());
+                            state.editing_index = None;
+                            state.text_edit_id = Some(id);
+                            ui.memory_mut(|m| m.request_focus(id));
+                        }
+                    },
+                    AnnotationTool::Pen | AnnotationTool::Highlighter => {
+                        state.start_pos = Some(pos);
+                        state.current_path = vec![pos];
+                    },
+                    AnnotationTool::Marker => {
+                        let number = state.annotations.iter()
+                            .filter_map(|annotation| match annotation {
+                                Annotation::Marker { number, .. } => Some(*number),
+                                _ => None,
+                            })
+                            .max()
+                            .unwrap_or(0) + 1;
+                        state.add(Annotation::Marker { pos, number, style: state.style });
+                    },
+                    AnnotationTool::None | AnnotationTool::Magnifier => {},
+                    _ => {
+                        state.start_pos = Some(pos);
+                    }
+                }
+            } else if mouse_down && state.drag.is_some() {
+                if let Some(drag) = &state.drag {
+                    let updated = match drag.handle {
+                        Some(handle) => drag.before.with_handle_moved(handle, pos),
+                        None => drag.before.translated(pos - drag.origin),
+                    };
+                    if let Some(annotation) = state.annotations.get_mut(drag.index) {
+                        *annotation = updated;
+                    }
+                }
+            } else if mouse_down && state.start_pos.is_some() {
+                match state.active_tool {
+                    // Scarta i punti troppo vicini per non appesantire il tratto
+                    AnnotationTool::Pen | AnnotationTool::Highlighter
+                        if state.current_path.last().is_none_or(|last| last.distance(pos) * scale >= 2.0) => {
+                        state.current_path.push(pos);
+                    },
+                    AnnotationTool::Eraser => {
+                        if let Some(index) = state.annotations.iter().rposition(|annotation| annotation.hit(pos, tolerance)) {
+                            state.remove(index);
+                        }
+                    },
+                    _ => {}
                 }
             } else if mouse_released {
-                if let Some(start) = self.annotation_state.start_pos {
-                    match self.annotation_state.active_tool {
+                if let Some(start) = state.start_pos {
+                    match state.active_tool {
                         AnnotationTool::Rectangle => {
                             let rect = Rect::from_two_pos(start, pos);
-                            self.annotation_state.annotations.push(Annotation::Rectangle {
+                            state.add(Annotation::Rectangle {
                                 rect,
-                                color: Color32::WHITE,
+                                style: state.style,
                             });
-                            self.annotation_state.start_pos = None;
-                            self.annotation_state.end_pos = None;
+                            state.start_pos = None;
+                            state.end_pos = None;
                         },
                         AnnotationTool::Arrow => {
-                            self.annotation_state.annotations.push(Annotation::Arrow {
+                            state.add(Annotation::Arrow {
                                 start,
                                 end: pos,
-                                color: Color32::WHITE,
+                                style: state.style,
                             });
-                            self.annotation_state.start_pos = None;
-                            self.annotation_state.end_pos = None;
+                            state.start_pos = None;
+                            state.end_pos = None;
 
                         },
-                        AnnotationTool::Text => {
+                        AnnotationTool::Line => {
+                            state.add(Annotation::Line {
+                                start,
+                                end: pos,
+                                style: state.style,
+                            });
+                            state.start_pos = None;
+                            state.end_pos = None;
+                        },
+                        AnnotationTool::Ellipse => {
+                            state.add(Annotation::Ellipse {
+                                rect: Rect::from_two_pos(start, pos),
+                                style: state.style,
+                            });
+                            state.start_pos = None;
+                            state.end_pos = None;
+                        },
+                        AnnotationTool::Pen | AnnotationTool::Highlighter => {
+                            let style = if state.active_tool == AnnotationTool::Highlighter {
+                                Self::highlighter_style(&state.style)
+                            } else {
+                                state.style
+                            };
+                            let points = smooth_path(&std::mem::take(&mut state.current_path));
+                            state.add(Annotation::Freehand { points, style });
+                            state.start_pos = None;
+                        },
+                        AnnotationTool::Eraser => {
+                            state.start_pos = None;
+                        },
+                        AnnotationTool::Spotlight => {
+                            // Un solo spotlight alla volta: quello nuovo sostituisce il precedente
+                            let spotlight = Annotation::Spotlight {
+                                rect: Rect::from_two_pos(start, pos),
+                                ellipse: state.spotlight_ellipse,
+                                style: AnnotationStyle { fill: Some(SPOTLIGHT_DIM), ..state.style },
+                            };
+                            match state.annotations.iter().position(|annotation| matches!(annotation, Annotation::Spotlight { .. })) {
+                                Some(index) => state.replace(index, spotlight),
+                                None => state.add(spotlight),
+                            }
+                            state.start_pos = None;
                         },
                         _ => {}
                     }
@@ -438,66 +2223,124 @@ impl MyApp {
             }
         }
 
-        if let Some(start) = self.annotation_state.start_pos {
-            if self.annotation_state.active_tool == AnnotationTool::Text {
-                if let Some(editing_text) = &mut self.annotation_state.editing_text {
-                    let text_edit = egui::TextEdit::singleline(editing_text)
-                        .desired_width(200.0)
-                        .font(FontId::proportional(14.0));
+        if state.active_tool == AnnotationTool::Laser && mouse_down {
+            if let Some(pos) = pointer_pos.filter(|pos| to_screen.from().contains(*pos)) {
+                state.laser_trail.push((pos, Instant::now()));
+            }
+        }
+        state.laser_trail.retain(|(_, time)| time.elapsed().as_secs_f32() < LASER_TRAIL_SECS);
+        state.fading.retain(|fading| fading.remaining() > 0.0);
+
+        // Ridisegna solo finché qualcosa si sta dissolvendo, oppure quando la prossima dissolvenza deve iniziare
+        if !state.laser_trail.is_empty() || state.fading.iter().any(|fading| fading.remaining() <= FADE_OUT_SECS) {
+            ui.ctx().request_repaint();
+        } else if let Some(next) = state.fading.iter().map(|fading| fading.remaining() - FADE_OUT_SECS).reduce(f32::min) {
+            ui.ctx().request_repaint_after(Duration::from_secs_f32(next.max(0.0)));
+        }
 
-                    let response = ui.put(
-                        Rect::from_min_size(start, egui::Vec2::new(200.0, 20.0)),
-                        text_edit
-                    );
+        // Alla fine del trascinamento la modifica viene registrata nella cronologia come un unico passo
+        if mouse_released {
+            if let Some(drag) = state.drag.take() {
+                if let Some(after) = state.annotations.get(drag.index).cloned() {
+                    state.annotations[drag.index] = drag.before;
+                    state.replace(drag.index, after);
+                }
+            }
+        }
 
-                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                        if !editing_text.is_empty() {
-                            self.annotation_state.annotations.push(Annotation::Text {
-                                pos: start,
-                                content: editing_text.clone(),
-                                is_editing: true,
-                                color: Color32::WHITE,
-                            });
-                        }
-                        self.annotation_state.editing_text = None;
-                        self.annotation_state.text_edit_id = None;
-                        self.annotation_state.start_pos = None;
-                    }
+        if let (Some(start), Some(id)) = (state.start_pos, state.text_edit_id) {
+            let style = state.editing_index
+                .and_then(|index| state.annotations.get(index))
+                .map(|annotation| *annotation.style())
+                .unwrap_or(state.style);
+            if let Some(editing_text) = &mut state.editing_text {
+                let rows = editing_text.split('\n').count();
+                let text_edit = egui::TextEdit::multiline(editing_text)
+                    .id(id)
+                    .desired_width(300.0)
+                    .desired_rows(rows)
+                    .font(FontId::proportional(style.font_size * scale))
+                    .text_color(style.color);
+
+                let response = ui.put(
+                    Rect::from_min_size(to_screen * start, egui::Vec2::new(300.0, (rows as f32 * style.font_size * 1.3 + 8.0) * scale)),
+                    text_edit
+                );
+
+                // Invio va a capo; Ctrl+Invio o un clic fuori confermano, Esc annulla
+                let confirmed = response.has_focus()
+                    && ui.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Enter));
+                if confirmed || response.lost_focus() {
+                    let cancelled = !confirmed && ui.input(|i| i.key_pressed(egui::Key::Escape));
+                    Self::finish_text_edit(state, !cancelled);
                 }
             }
         }
+
         // Draw existing annotations
         let painter = ui.painter();
-        for annotation in &self.annotation_state.annotations {
-            match annotation {
-                Annotation::Rectangle{rect, ..} => {
-                    painter.rect_stroke(*rect, 0.0, egui::Stroke::new(2.0, Color32::WHITE));
-                },
-                Annotation::Arrow { start, end, .. } => {
-                    Self::draw_arrow(&painter, *start, *end, Color32::WHITE);
-                },
-                Annotation::Text { pos, content, .. } => {
-                    painter.text(
-                        *pos,
-                        egui::Align2::LEFT_TOP,
-                        content,
-                        FontId::proportional(14.0),
-                        Color32::WHITE,
-                    );
-                },
+        match state.editing_index {
+            Some(editing) => {
+                let visible: Vec<Annotation> = state.annotations.iter().enumerate()
+                    .filter(|(index, _)| *index != editing)
+                    .map(|(_, annotation)| annotation.clone())
+                    .collect();
+                Self::paint_annotations(painter, &visible, to_screen);
+            },
+            None => Self::paint_annotations(painter, &state.annotations, to_screen),
+        }
+        let fading: Vec<Annotation> = state.fading.iter()
+            .map(|fading| fading.annotation.with_opacity(fading.remaining() / FADE_OUT_SECS))
+            .collect();
+        Self::paint_annotations(painter, &fading, to_screen);
+        Self::paint_laser(painter, &state.laser_trail, to_screen);
+
+        if state.active_tool == AnnotationTool::Select {
+            if let Some(annotation) = state.selected.and_then(|index| state.annotations.get(index)) {
+                let selection = ui.visuals().selection.stroke.color;
+                painter.rect_stroke(to_screen.transform_rect(annotation.bounds()).expand(4.0), 0.0, egui::Stroke::new(1.0, selection));
+                for handle in annotation.handles() {
+                    let handle_rect = Rect::from_center_size(to_screen * handle, egui::Vec2::splat(8.0));
+                    painter.rect(handle_rect, 1.0, Color32::WHITE, egui::Stroke::new(1.0, selection));
+                }
             }
         }
 
-        if let (Some(start), Some(current_pos)) = (self.annotation_state.start_pos, pointer_pos) {
-            match self.annotation_state.active_tool {
+        if let (Some(start), Some(current_pos)) = (state.start_pos, pointer_pos) {
+            match state.active_tool {
                 AnnotationTool::Rectangle => {
                     let rect = Rect::from_two_pos(start, current_pos);
-                    painter.rect_stroke(rect, 0.0, egui::Stroke::new(2.0, Color32::WHITE));
+                    painter.rect(
+                        to_screen.transform_rect(rect),
+                        0.0,
+                        state.style.fill.unwrap_or(Color32::TRANSPARENT),
+                        egui::Stroke::new(state.style.stroke_width * scale, state.style.color),
+                    );
                 },
                 AnnotationTool::Arrow => {
-                    Self::draw_arrow(&painter, start, current_pos, Color32::WHITE);
+                    Self::draw_arrow(painter, to_screen * start, to_screen * current_pos, state.style.color, state.style.stroke_width * scale);
+                },
+                AnnotationTool::Line => {
+                    painter.line_segment([to_screen * start, to_screen * current_pos], egui::Stroke::new(state.style.stroke_width * scale, state.style.color));
+                },
+                AnnotationTool::Ellipse => {
+                    let rect = to_screen.transform_rect(Rect::from_two_pos(start, current_pos));
+                    painter.add(egui::Shape::Ellipse(egui::epaint::EllipseShape {
+                        center: rect.center(),
+                        radius: rect.size() / 2.0,
+                        fill: state.style.fill.unwrap_or(Color32::TRANSPARENT),
+                        stroke: egui::Stroke::new(state.style.stroke_width * scale, state.style.color),
+                    }));
                 },
-                AnnotationTool::Text => {
+                AnnotationTool::Pen => {
+                    Self::draw_freehand(painter, &state.current_path, &state.style, to_screen);
+                },
+                AnnotationTool::Highlighter => {
+                    Self::draw_freehand(painter, &state.current_path, &Self::highlighter_style(&state.style), to_screen);
+                },
+                AnnotationTool::Spotlight => {
+                    let hole = to_screen.transform_rect(Rect::from_two_pos(start, current_pos));
+                    Self::paint_spotlight(painter, hole, state.spotlight_ellipse, *to_screen.to(), SPOTLIGHT_DIM);
                 },
                 _ => {}
             }
@@ -505,6 +2348,89 @@ impl MyApp {
 
     }
 
+    // Scia luminosa che si assottiglia e sbiadisce con l'età dei punti
+    fn paint_laser(painter: &egui::Painter, trail: &[(Pos2, Instant)], to_screen: RectTransform) {
+        let scale = to_screen.scale().x;
+        for pair in trail.windows(2) {
+            let life = 1.0 - pair[1].1.elapsed().as_secs_f32() / LASER_TRAIL_SECS;
+            let alpha = (life.clamp(0.0, 1.0) * 255.0) as u8;
+            let segment = [to_screen * pair[0].0, to_screen * pair[1].0];
+            painter.line_segment(segment, egui::Stroke::new(12.0 * scale * life, Color32::from_rgba_unmultiplied(255, 0, 0, alpha / 4)));
+            painter.line_segment(segment, egui::Stroke::new(4.0 * scale * life.max(0.3), Color32::from_rgba_unmultiplied(255, 40, 40, alpha)));
+        }
+        if let Some((pos, _)) = trail.last() {
+            let center = to_screen * *pos;
+            painter.circle_filled(center, 12.0 * scale, Color32::from_rgba_unmultiplied(255, 0, 0, 60));
+            painter.circle_filled(center, 5.0 * scale, Color32::from_rgb(255, 60, 60));
+        }
+    }
+
+    fn text_at(state: &AnnotationState, pos: Pos2, tolerance: f32) -> Option<usize> {
+        state.annotations.iter()
+            .rposition(|annotation| matches!(annotation, Annotation::Text { .. }) && annotation.hit(pos, tolerance))
+    }
+
+    // Sceglie la maniglia dell'annotazione selezionata sotto il puntatore, altrimenti seleziona l'annotazione più in alto
+    fn begin_drag(state: &mut AnnotationState, pos: Pos2, tolerance: f32) {
+        if let Some(index) = state.selected {
+            if let Some(annotation) = state.annotations.get(index) {
+                if let Some(handle) = annotation.handles().iter().position(|handle| handle.distance(pos) <= tolerance * 1.5) {
+                    state.drag = Some(AnnotationDrag { index, handle: Some(handle), origin: pos, before: annotation.clone() });
+                    return;
+                }
+            }
+        }
+        state.selected = state.annotations.iter().rposition(|annotation| annotation.hit(pos, tolerance));
+        state.drag = state.selected.map(|index| AnnotationDrag {
+            index,
+            handle: None,
+            origin: pos,
+            before: state.annotations[index].clone(),
+        });
+    }
+
+    fn start_text_edit(state: &mut AnnotationState, ui: &egui::Ui, index: usize) {
+        if let Some(Annotation::Text { pos, content, .. }) = state.annotations.get(index) {
+            let id = egui::Id::new("text_edit");
+            state.start_pos = Some(*pos);
+            state.editing_text = Some(content.clone());
+            state.editing_index = Some(index);
+            state.text_edit_id = Some(id);
+            state.selected = Some(index);
+            state.drag = None;
+            ui.memory_mut(|m| m.request_focus(id));
+        }
+    }
+
+    // Un testo svuotato viene eliminato; con keep a false le modifiche vengono scartate
+    fn finish_text_edit(state: &mut AnnotationState, keep: bool) {
+        let content = state.editing_text.take().unwrap_or_default();
+        let start = state.start_pos.take();
+        state.text_edit_id = None;
+        if !keep {
+            state.editing_index = None;
+            return;
+        }
+        match state.editing_index.take() {
+            Some(index) => {
+                if let Some(Annotation::Text { pos, style, .. }) = state.annotations.get(index) {
+                    let (pos, style) = (*pos, *style);
+                    if content.trim().is_empty() {
+                        state.remove(index);
+                        state.selected = None;
+                    } else {
+                        state.replace(index, Annotation::Text { pos, content, style });
+                    }
+                }
+            },
+            None => {
+                if let Some(pos) = start.filter(|_| !content.trim().is_empty()) {
+                    state.add(Annotation::Text { pos, content, style: state.style });
+                }
+            },
+        }
+    }
+
 
     fn save_original_window_state(&self, ctx: &egui::Context) {
         ctx.send_viewport_cmd(egui::ViewportCommand::Transparent(false));
@@ -522,6 +2448,8 @@ impl MyApp {
         "\nShortcuts:\n\
         Fn + F1 --> Metti in pausa lo stream\n\
         Fn + F2--> Blank screen\n\
+        Ctrl + 1..9 --> Passa alla scena corrispondente\n\
+        F3 / F4 (PagSu / PagGiù) --> Slide precedente / successiva\n\
         ESC --> Interrompi lo stream\n"
             .to_string()
 
@@ -533,6 +2461,7 @@ impl App for MyApp {
         [0.0, 0.0, 0.0, 0.0]
     }
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
+        self.guard_close(ctx);
         if self.selected_display_index==None{
             self.refresh_displays()
         }
@@ -596,13 +2525,19 @@ impl App for MyApp {
                         self.handle_selection(ctx, image_rect);
                     }
                 });
+        } else if self.whiteboard_visible {
+            self.show_whiteboard(ctx);
         } else if self.toolbar_visible==true && self.caster_running.load(Ordering::SeqCst) {
                 self.set_fullscreen_transparent(ctx);
                 egui::CentralPanel::default()
                     .frame(egui::Frame::none()
                     .fill(Color32::from_rgba_unmultiplied(0, 0, 0, 0)))
                     .show(ctx, |ui| {
-                        self.handle_annotations(ui);
+                        let screen_rect = ui.max_rect();
+                        Self::handle_annotations(&mut self.annotation_state, ui, RectTransform::identity(screen_rect));
+                        if self.annotation_state.active_tool == AnnotationTool::Magnifier {
+                            self.show_magnifier(ui);
+                        }
                     });
                 egui::Window::new("")
                 .fixed_size(egui::Vec2::new(300.0, 45.0))
@@ -616,7 +2551,12 @@ impl App for MyApp {
                     .stroke(egui::Stroke::new(1.0, Color32::from_rgba_unmultiplied(255, 255, 255, 60))))
                 .show(ctx, |ui| {
                     ui.horizontal(|ui| {
-                        self.show_annotation_toolbar(ui);
+                        ui.vertical(|ui| {
+                            if let Some(action) = Self::show_annotation_toolbar(&mut self.annotation_state, ui, true) {
+                                self.handle_annotation_file_action(ctx, action, false);
+                            }
+                            self.show_burn_controls(ui);
+                        });
                         ui.separator();
                         if ui.button("❌").clicked() {
                             self.toolbar_visible = false;
@@ -648,6 +2588,8 @@ impl App for MyApp {
                         self.status_message = "Modalità selezionata: Receiver".to_string();
                     }
                 });
+                self.show_unfinished_recordings(ui);
+                self.show_conversion_jobs(ui);
 
                 if let Some(ref mode) = self.mode {
                     match mode {
@@ -659,7 +2601,7 @@ impl App for MyApp {
                             });
                             ui.horizontal(|ui| {
                                 ui.label("Seleziona Monitor:");
-                                if !self.caster_running.load(Ordering::SeqCst) {
+                                if !self.combine_displays {
                                     egui::ComboBox::from_label("")
                                         .selected_text(match self.selected_display_index {
                                             Some(index) => &self.available_displays[index].name,
@@ -675,25 +2617,45 @@ impl App for MyApp {
 
                                                 if response.clicked() {
                                                     self.selected_area = None;
+                                                    if let Ok(mut settings) = self.capture_settings.write() {
+                                                        settings.selected_area = None;
+                                                        settings.display_index = index;
+                                                    }
                                                 }
                                             }
                                         });
-                                } else {
-                                    // Mostra una versione disabilitata del combobox
-                                    ui.add_enabled(
-                                        false,
-                                        egui::Label::new(match self.selected_display_index {
-                                            Some(index) => &self.available_displays[index].name,
-                                            None => "Seleziona un monitor",
-                                        })
-                                    );
                                 }
-
                                 if ui.add_enabled(!self.caster_running.load(Ordering::SeqCst), egui::Button::new("🔄")).clicked() {
                                     self.selected_area = None;
+                                    self.combined_displays.clear();
                                     self.refresh_displays();
                                 }
                             });
+                            if ui.checkbox(&mut self.combine_displays, "Combina più monitor in un unico stream").changed() {
+                                self.selected_area = None;
+                                self.update_capture_settings();
+                            }
+                            if self.combine_displays {
+                                self.show_display_layout(ui);
+                            }
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Sorgente:");
+                                if let Ok(mut settings) = self.capture_settings.write() {
+                                    ui.selectable_value(&mut settings.source, CaptureSource::Screen, "🖥 Schermo");
+                                    ui.selectable_value(&mut settings.source, CaptureSource::Scenes, "🎬 Scene");
+                                    ui.selectable_value(&mut settings.source, CaptureSource::Slideshow, "🖼 Presentazione");
+                                    ui.selectable_value(&mut settings.source, CaptureSource::Whiteboard, "✏ Lavagna");
+                                }
+                            });
+                            let source = self.capture_settings.read().unwrap().source;
+                            match source {
+                                CaptureSource::Screen => {}
+                                CaptureSource::Scenes => self.show_scene_editor(ui, ctx),
+                                CaptureSource::Slideshow => self.show_slideshow_controls(ui),
+                                CaptureSource::Whiteboard => self.show_whiteboard_controls(ui),
+                            }
+                            ui.separator();
 
                             if let Some(area) = self.selected_area {
                                 ui.label(format!(
@@ -709,7 +2671,7 @@ impl App for MyApp {
                                 self.status_message="Modalità selezionata: Caster".to_string();
 
                                 let select_area_button = ui.add_enabled(
-                                    self.selected_display_index.is_some(),
+                                    !self.current_placements().is_empty(),
                                     egui::Button::new("Seleziona area")
                                 );
 
@@ -738,12 +2700,12 @@ impl App for MyApp {
 
                                     let stop_signal = self.stop_signal.clone();
                                     let ctx = ctx.clone();
-                                    let selected_area = self.selected_area;
+                                    self.update_capture_settings();
+                                    let capture_settings = self.capture_settings.clone();
                                     let caster_address = self.caster_address.clone();
                                     let error_message = self.error_message.clone();
                                     let is_error = self.is_error.clone();
                                     let is_running = self.caster_running.clone(); // Assicurati di usare caster_running
-                                    let selected_display_index = self.selected_display_index.unwrap_or_else(|| 0);
                                     let paused_clone = self.paused.clone();
                                     let screen_blanked_clone = self.screen_blanked.clone();
                                     let terminate_clone = self.terminate.clone();
@@ -752,7 +2714,7 @@ impl App for MyApp {
 
                                     std::thread::spawn(move || {
                                         Runtime::new().unwrap().block_on(async {
-                                            if let Err(e) = caster::start_caster(&caster_address, stop_signal, selected_area, selected_display_index, paused_clone, screen_blanked_clone, terminate_clone).await {
+                                            if let Err(e) = caster::start_caster(&caster_address, stop_signal, capture_settings, paused_clone, screen_blanked_clone, terminate_clone).await {
                                                 let error = format!("Errore nel caster: {}", e);
                                                 *error_message.write().unwrap() = Some(error);
                                                 is_error.store(true, Ordering::SeqCst);
@@ -766,6 +2728,19 @@ impl App for MyApp {
                                 }
                             } else {
 
+                                ui.horizontal(|ui| {
+                                    if ui.button("Cambia area").clicked() {
+                                        self.capture_screenshot(ctx);
+                                        self.selecting_area = true;
+                                        self.start_pos = None;
+                                        self.status_message = "Clicca e trascina per selezionare l'area".to_string();
+                                    }
+                                    if ui.add_enabled(self.selected_area.is_some(), egui::Button::new("Schermo intero")).clicked() {
+                                        self.selected_area = None;
+                                        self.update_capture_settings();
+                                    }
+                                });
+                                self.show_burn_controls(ui);
                                 ui.horizontal(|ui| {
                                     if ui.button(if self.toolbar_visible {"Nascondi Toolbar"} else {"Mostra Toolbar"}).clicked() {
                                         self.toolbar_visible = !self.toolbar_visible;
@@ -854,6 +2829,21 @@ impl App for MyApp {
                             } else if self.connected_to_caster.load(Ordering::SeqCst){
                                 ui.horizontal(|ui| {
                                     if let Ok(mut receiver_state) = Arc::clone(&self.receiver_state).write() {
+                                        if let Some(error) = receiver_state.recording_error.take() {
+                                            self.handle_recording_error(error);
+                                        }
+                                        let previous_settings = receiver_state.settings.clone();
+
+                                        let replay_seconds = receiver_state.settings.replay_seconds;
+                                        let replay_available = receiver_state.replay.duration().min(Duration::from_secs(replay_seconds as u64));
+                                        if replay_seconds > 0 {
+                                            let hotkey = ui.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::F9));
+                                            let button = ui.add_enabled(!replay_available.is_zero(), egui::Button::new(format!("⏪ Salva ultimi {} s", replay_seconds)))
+                                                .on_hover_text(format!("Salva lo stream già ricevuto (F9). Disponibili: {} s", replay_available.as_secs()));
+                                            if (button.clicked() || hotkey) && !replay_available.is_zero() {
+                                                self.save_replay(&receiver_state);
+                                            }
+                                        }
 
                                         if receiver_state.recording {
                                             if ui.add(egui::Button::new("⏹ Arresta Registrazione")
@@ -862,7 +2852,7 @@ impl App for MyApp {
                                             {
                                                 match receiver_state.stop_recording() {
                                                     Ok(_) => {
-                                                        self.status_message = "Registrazione completata con successo.".to_string();
+                                                        self.status_message = "Registrazione arrestata: il video sarà pronto al termine della conversione.".to_string();
                                                         self.clear_error();
                                                     },
                                                     Err(e) => {
@@ -872,46 +2862,93 @@ impl App for MyApp {
                                             }
                                             // Mostra stato registrazione
                                             ui.label(format!("Frame registrati: {}", receiver_state.frame_count));
+                                            if receiver_state.dropped_frames > 0 {
+                                                ui.label(format!("Frame scartati: {}", receiver_state.dropped_frames))
+                                                    .on_hover_text("FFmpeg non riesce a codificare alla velocità dello stream");
+                                            }
                                         } else {
                                             ui.horizontal(|ui| {
-                                                if ui.add(egui::Button::new("⏺ Avvia Registrazione")
+                                                egui::ComboBox::from_id_salt("recording_format")
+                                                    .selected_text(receiver_state.settings.format.label())
+                                                    .show_ui(ui, |ui| {
+                                                        for format in RecordingFormat::ALL {
+                                                            ui.selectable_value(&mut receiver_state.settings.format, format, format.label());
+                                                        }
+                                                    });
+                                                ui.checkbox(&mut receiver_state.settings.cut_pauses, "Taglia le pause")
+                                                    .on_hover_text("Se disattivato le pause dello stream restano nel video come fermo immagine");
+                                                egui::ComboBox::from_id_salt("resolution_change")
+                                                    .selected_text(receiver_state.settings.on_resolution_change.label())
+                                                    .show_ui(ui, |ui| {
+                                                        for mode in [ResolutionChange::Letterbox, ResolutionChange::NewSegment] {
+                                                            ui.selectable_value(&mut receiver_state.settings.on_resolution_change, mode, mode.label());
+                                                        }
+                                                    })
+                                                    .response
+                                                    .on_hover_text("Cosa fare se il caster cambia risoluzione durante la registrazione");
+                                                ui.label("Nuovo file ogni");
+                                                ui.add(egui::DragValue::new(&mut receiver_state.settings.rotation.max_minutes).range(0..=600).suffix(" min"))
+                                                    .on_hover_text("0 = nessun limite di durata");
+                                                ui.label("o");
+                                                ui.add(egui::DragValue::new(&mut receiver_state.settings.rotation.max_megabytes).range(0..=100_000).suffix(" MB"))
+                                                    .on_hover_text("0 = nessun limite di dimensione");
+                                                if ui.button("⚙ Impostazioni").clicked() {
+                                                    self.recording_settings_draft = match self.recording_settings_draft {
+                                                        Some(_) => None,
+                                                        None => Some(receiver_state.settings.clone()),
+                                                    };
+                                                }
+                                                let starting = self.settings_check.as_ref().is_some_and(|(purpose, _)| *purpose == SettingsCheck::Start);
+                                                if ui.add_enabled(!starting, egui::Button::new("⏺ Avvia Registrazione")
                                                     .fill(Color32::from_rgb(50, 255, 50)))
                                                     .clicked()
                                                 {
-                                                    match std::process::Command::new("ffmpeg").arg("-version").output() {
-                                                        Ok(_) => {
-                                                            match receiver_state.start_recording() {
-                                                                Ok(_) => {
-                                                                    self.status_message = "Registrazione avviata.".to_string();
-                                                                    self.clear_error();
-                                                                },
-                                                                Err(e) => {
-                                                                    self.handle_recording_error(e.to_string());
-                                                                }
-                                                            }
-                                                        },
-                                                        Err(_) => {
-                                                            self.handle_recording_error(
-                                                                "FFmpeg non trovato. Installare FFmpeg per abilitare la registrazione video."
-                                                                    .to_string()
-                                                            );
-                                                        }
-                                                    }
-
+                                                    self.status_message = "Verifica delle impostazioni di registrazione...".to_string();
+                                                    self.check_recording_settings(ctx, receiver_state.settings.clone(), SettingsCheck::Start);
+                                                }
+                                                if starting {
+                                                    ui.spinner();
                                                 }
                                                 if ui.button("⏹ Stop").clicked() {
+                                                    self.time_shift = None;
                                                     self.status_message = "Interrompendo il receiver...".to_string();
                                                     self.stop_signal.store(true, Ordering::SeqCst);
                                                     self.receiver_running.store(false,Ordering::SeqCst);
                                                 }
                                             });
                                         }
+                                        if receiver_state.settings != previous_settings {
+                                            self.recording_settings_unsaved = true;
+                                        }
                                     }
                                 });
+                                self.show_recording_settings(ctx);
 
 
+                                if let Ok(receiver_state) = self.receiver_state.read() {
+                                    if receiver_state.recording {
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("⚫ REC")
+                                                .color(Color32::from_rgb(255, 0, 0))
+                                                .strong());
+                                            ui.label(format!("Salvando in: {}", receiver_state.output_dir));
+                                            if receiver_state.segment() > 0 {
+                                                ui.label(format!("Segmento {}", receiver_state.segment() + 1));
+                                            }
+                                        });
+                                    }
+                                }
+
+                                ui.checkbox(&mut self.show_remote_annotations, "Mostra annotazioni del caster");
+                                let shifted = self.time_shift.is_some();
+                                self.show_timeline(ui);
                                 if let Ok(mut shared) = self.shared_frame.write() {
-                                    if shared.new_frame {
+                                    // Tornando alla diretta si mostra subito l'ultimo frame ricevuto al posto di quello della cronologia
+                                    if shifted && self.time_shift.is_none() && !shared.buffer.is_empty() {
+                                        shared.new_frame = true;
+                                    }
+                                    // In visione differita la texture viene dalla cronologia; il frame in diretta resta in attesa
+                                    if shared.new_frame && self.time_shift.is_none() {
                                         let color_image = egui::ColorImage::from_rgba_unmultiplied(
                                             [shared.width, shared.height],
                                             &shared.buffer,
@@ -926,17 +2963,6 @@ impl App for MyApp {
                                     }
                                 }
 
-                                if let Ok(receiver_state) = self.receiver_state.read() {
-                                    if receiver_state.recording {
-                                        ui.horizontal(|ui| {
-                                            ui.label(egui::RichText::new("⚫ REC")
-                                                .color(Color32::from_rgb(255, 0, 0))
-                                                .strong());
-                                            ui.label(format!("Salvando in: {}", receiver_state.output_dir));
-                                        });
-                                    }
-                                }
-
                                 if let Some(texture) = &self.stream_texture {
                                     let available_size = ui.available_size();
                                     let texture_size = texture.size_vec2();
@@ -961,7 +2987,18 @@ impl App for MyApp {
 
                                     let image = Image::from_texture(texture)
                                         .fit_to_exact_size(display_size);
-                                    image.ui(ui);
+                                    let image_rect = image.ui(ui).rect;
+
+                                    // Le annotazioni ricevute sono quelle della diretta
+                                    if self.show_remote_annotations && self.time_shift.is_none() {
+                                        if let Ok(shared) = self.shared_frame.read() {
+                                            let to_screen = RectTransform::from_to(
+                                                Rect::from_min_size(Pos2::ZERO, texture_size),
+                                                image_rect,
+                                            );
+                                            Self::paint_annotations(&ui.painter_at(image_rect), &shared.annotations, to_screen);
+                                        }
+                                    }
                                 }
                                 ctx.request_repaint();
                             }
@@ -972,5 +3009,10 @@ impl App for MyApp {
                 ui.label(&self.status_message);
             });
         }
+        self.follow_display_errors();
+        self.follow_settings_check(ctx);
+        self.follow_recording_settings(ctx);
+        self.follow_content_page(ctx);
+        self.sync_overlay_annotations(ctx);
     }
 }
\ No newline at end of file
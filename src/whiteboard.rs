@@ -0,0 +1,172 @@
+use std::io;
+use std::path::Path;
+use eframe::egui::{Color32, Pos2};
+use image::{ImageResult, Rgba, RgbaImage};
+use crate::raster;
+use crate::annotation::Annotation;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GridStyle {
+    None,
+    Squares,
+    Lines,
+    Dots,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Whiteboard {
+    pub width: u32,
+    pub height: u32,
+    pub background: Color32,
+    pub grid: GridStyle,
+    pub grid_spacing: f32,
+    // Annotazioni salvate di ogni pagina, senza quelle temporanee
+    pub pages: Vec<Vec<Annotation>>,
+    pub current: usize,
+    // Annotazioni trasmesse per la pagina corrente: quelle salvate più le temporanee e la scia del laser
+    pub live: Vec<Annotation>,
+}
+
+impl Default for Whiteboard {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            background: Color32::WHITE,
+            grid: GridStyle::None,
+            grid_spacing: 40.0,
+            pages: vec![Vec::new()],
+            current: 0,
+            live: Vec::new(),
+        }
+    }
+}
+
+impl Whiteboard {
+    pub fn grid_color(&self) -> Color32 {
+        // Linee chiare su sfondo scuro e viceversa
+        let [r, g, b, _] = self.background.to_array();
+        if (r as u32 + g as u32 + b as u32) / 3 > 128 {
+            Color32::from_rgba_unmultiplied(0, 0, 0, 40)
+        } else {
+            Color32::from_rgba_unmultiplied(255, 255, 255, 40)
+        }
+    }
+
+    pub fn add_page(&mut self) {
+        self.current += 1;
+        self.pages.insert(self.current, Vec::new());
+    }
+
+    pub fn next(&mut self) {
+        if self.current + 1 < self.pages.len() {
+            self.current += 1;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        self.current = self.current.saturating_sub(1);
+    }
+
+    // Copia con la sola pagina trasmessa, da rasterizzare nel caster
+    pub(crate) fn live_page(&self) -> Whiteboard {
+        Whiteboard { pages: vec![self.live.clone()], current: 0, live: Vec::new(), ..*self }
+    }
+
+    pub fn render_page(&self, index: usize) -> RgbaImage {
+        let [r, g, b, a] = self.background.to_srgba_unmultiplied();
+        let mut img = RgbaImage::from_pixel(self.width, self.height, Rgba([r, g, b, a]));
+        self.draw_grid(&mut img);
+        if let Some(page) = self.pages.get(index) {
+            for annotation in page {
+                raster::draw_annotation(&mut img, annotation);
+            }
+        }
+        img
+    }
+
+    fn draw_grid(&self, img: &mut RgbaImage) {
+        let spacing = self.grid_spacing.max(4.0);
+        let color = self.grid_color();
+        let width = self.width as f32;
+        let height = self.height as f32;
+        match self.grid {
+            GridStyle::None => {}
+            GridStyle::Squares => {
+                let mut x = spacing;
+                while x < width {
+                    raster::draw_line(img, Pos2::new(x, 0.0), Pos2::new(x, height), 1.0, color);
+                    x += spacing;
+                }
+                let mut y = spacing;
+                while y < height {
+                    raster::draw_line(img, Pos2::new(0.0, y), Pos2::new(width, y), 1.0, color);
+                    y += spacing;
+                }
+            }
+            GridStyle::Lines => {
+                let mut y = spacing;
+                while y < height {
+                    raster::draw_line(img, Pos2::new(0.0, y), Pos2::new(width, y), 1.0, color);
+                    y += spacing;
+                }
+            }
+            GridStyle::Dots => {
+                let mut y = spacing;
+                while y < height {
+                    let mut x = spacing;
+                    while x < width {
+                        raster::draw_line(img, Pos2::new(x, y), Pos2::new(x, y), 3.0, color);
+                        x += spacing;
+                    }
+                    y += spacing;
+                }
+            }
+        }
+    }
+
+    pub fn export_page(&self, index: usize, path: &Path) -> ImageResult<()> {
+        self.render_page(index).save(path)
+    }
+}
+
+pub(crate) struct WhiteboardRenderer {
+    last: Option<Whiteboard>,
+}
+
+impl WhiteboardRenderer {
+    pub(crate) fn new() -> Self {
+        Self { last: None }
+    }
+
+    // Confronta la lavagna con l'ultima rasterizzata senza copiarla, così il caster clona la pagina solo se serve
+    pub(crate) fn changed(&self, whiteboard: &Whiteboard) -> bool {
+        self.last.as_ref().is_none_or(|last| {
+            (last.width, last.height, last.background, last.grid, last.grid_spacing)
+                != (whiteboard.width, whiteboard.height, whiteboard.background, whiteboard.grid, whiteboard.grid_spacing)
+                || last.pages.first() != Some(&whiteboard.live)
+        })
+    }
+
+    // Dimensioni dell'ultima pagina rasterizzata
+    pub(crate) fn size(&self) -> Option<(usize, usize)> {
+        self.last.as_ref().map(|last| (last.width as usize, last.height as usize))
+    }
+
+    // Restituisce la pagina di live_page in formato BGRA, oppure WouldBlock se la lavagna non è cambiata
+    pub(crate) fn frame(&mut self, whiteboard: &Whiteboard) -> io::Result<(Vec<u8>, usize, usize)> {
+        if self.last.as_ref() == Some(whiteboard) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.last = Some(whiteboard.clone());
+        let mut data = whiteboard.render_page(whiteboard.current).into_raw();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        Ok((data, whiteboard.width as usize, whiteboard.height as usize))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.last = None;
+    }
+}